@@ -0,0 +1,30 @@
+// generate initrd.rs, the user programs built by make.js are embedded into kernel
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+// binary in target/x86-unknown-user/release and path it is registered under
+const PROGRAMS: [(&str, &str); 2] = [
+    ("init", "/sbin/init"),
+    ("hello", "/bin/hello"),
+];
+
+fn main() {
+    let root = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("..");
+    let dir = root.join("target/x86-unknown-user/release");
+    let mut s = String::from("pub static IMAGES: &[(&str, &[u8])] = &[\n");
+
+    for (bin, path) in PROGRAMS {
+        let f = dir.join(bin);
+        println!("cargo:rerun-if-changed={}", f.display());
+        if !f.exists() {
+            println!("cargo:warning=user program {} is not built, {} is left out of initrd", bin, path);
+            continue;
+        }
+        s.push_str(&format!("    ({:?}, include_bytes!({:?})),\n", path, f.canonicalize().unwrap()));
+    }
+    s.push_str("];\n");
+
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("initrd.rs");
+    fs::write(out, s).unwrap();
+}
@@ -101,11 +101,14 @@ pub fn in_b(port: u16) -> u8 {
 pub fn out_sw(port: u16, buf: &[u16]) {
     unsafe {
         asm!(
+        // esi is reserved by llvm on x86
         "cld",
+        "xchg esi, {0}",
         "rep outsw",
+        "xchg esi, {0}",
+        inout(reg) buf.as_ptr() as usize => _,
         in("dx") port,
         in("ecx")  buf.len(),
-        in("edi")  buf.as_ptr() as usize
         )
     }
 }
@@ -133,12 +136,13 @@ pub fn lidt(addr: usize) {
     }
 }
 
-/// setup page, new stack top, then jump to callback
+/// setup page with cr3 value pde_start, new stack top, then jump to callback
 pub fn page_jmp(pde_start: usize, new_stack: usize, cb: usize) {
     unsafe {
         let mut cr0: u32;
         asm!("mov {}, cr0", out(reg) cr0);
-        cr0 |= 1 << 31;
+        // PG, and WP so that kernel writes also fault on read only user pages for copy on write
+        cr0 |= 1 << 31 | 1 << 16;
         asm!("mov cr3, {0}", in(reg) pde_start);
         asm!("mov cr0, {}", in(reg) cr0);
         asm!("mov ebp, {0}", "mov esp, ebp", in(reg) new_stack);
@@ -146,6 +150,49 @@ pub fn page_jmp(pde_start: usize, new_stack: usize, cb: usize) {
     }
 }
 
+// execute cpuid with leaf in eax, return (eax, ebx, ecx, edx)
+pub fn cpuid(leaf: u32) -> (u32, u32, u32, u32) {
+    let (a, b, c, d): (u32, u32, u32, u32);
+    unsafe { asm!("cpuid", inout("eax") leaf => a, out("ebx") b, inout("ecx") 0 => c, out("edx") d) };
+    (a, b, c, d)
+}
+
+// set CR4.PSE, page directory entries with PS bit map 4MB pages
+pub fn enable_pse() {
+    unsafe { asm!("mov {0}, cr4", "or {0}, 0x10", "mov cr4, {0}", out(reg) _) };
+}
+
+// set CR4.PAE, three level page tables with 64 bits entries
+pub fn enable_pae() {
+    unsafe { asm!("mov {0}, cr4", "or {0}, 0x20", "mov cr4, {0}", out(reg) _) };
+}
+
+// set EFER.NXE, bit 63 of pae entries disables instruction fetch
+pub fn enable_nx() {
+    unsafe {
+        asm!(
+        "rdmsr",
+        "or eax, 0x800",
+        "wrmsr",
+        in("ecx") 0xc0000080u32,
+        out("eax") _,
+        out("edx") _,
+        )
+    }
+}
+
+// linear address which caused the last page fault
+pub fn cr2() -> usize {
+    let r: usize;
+    unsafe { asm!("mov {}, cr2", out(reg) r) };
+    r
+}
+
+// flush tlb entry of virtual address v
+pub fn invlpg(v: usize) {
+    unsafe { asm!("invlpg [{}]", in(reg) v) };
+}
+
 pub fn int_entries() -> usize {
     api_call(methods::INT_ENTRIES_OFF, &[]) as usize
 }
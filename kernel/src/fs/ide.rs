@@ -6,8 +6,8 @@ use crate::thread::current_pcb;
 use crate::{c_print, c_println, println, sleep_mils};
 use crate::asm::out_b;
 use crate::err::SE;
-use crate::fs::ctl::{BIT_DEV_DEV, BIT_DEV_LBA, BIT_DEV_MBS, BIT_STAT_BSY, BIT_STAT_DRQ, CMD_ID, CMD_READ_SEC};
-use crate::fs::DiskInfo;
+use crate::fs::ctl::{BIT_DEV_DEV, BIT_DEV_LBA, BIT_DEV_MBS, BIT_STAT_BSY, BIT_STAT_DRQ, CMD_ID, CMD_READ_SEC, CMD_WRITE_SEC};
+use crate::fs::{BootSec, DiskInfo};
 use crate::int::register;
 use crate::thread::reg::IntCtx;
 use crate::thread::sync::{Lock, Semaphore};
@@ -40,6 +40,8 @@ pub struct Partition {
     blocks: Option<&'static mut [u8]>,
     // bitmap of blocks
     inodes: Option<&'static mut [u8]>, // inode bitmaps
+    fs_type: u8,
+    // system id in partition table
 }
 
 impl Node for Partition {
@@ -52,6 +54,40 @@ impl Node for Partition {
     }
 }
 
+impl Partition {
+    pub fn disk(&self) -> &'static mut Disk {
+        cst!(self.disk)
+    }
+
+    pub fn name(&self) -> &str {
+        as_str(&self.name)
+    }
+
+    pub fn fs_type(&self) -> u8 {
+        self.fs_type
+    }
+
+    pub fn start(&self) -> u32 {
+        self.start
+    }
+
+    pub fn sectors(&self) -> u32 {
+        self.sec_n
+    }
+
+    // read sectors relative to start of the partition
+    pub fn read(&self, lba: u32, buf: &mut [u8], sec_n: usize) -> Result<(), SE> {
+        assert!(lba as usize + sec_n <= self.sec_n as usize, "lba {} out of partition {}", lba, self.name());
+        self.disk().ide_read(self.start + lba, buf, sec_n)
+    }
+
+    // write sectors relative to start of the partition
+    pub fn write(&self, lba: u32, buf: &[u8], sec_n: usize) -> Result<(), SE> {
+        assert!(lba as usize + sec_n <= self.sec_n as usize, "lba {} out of partition {}", lba, self.name());
+        self.disk().ide_write(self.start + lba, buf, sec_n)
+    }
+}
+
 pub struct Disk {
     pub name: [u8; NAME_BUF_LEN],
     // name of this disk
@@ -85,7 +121,9 @@ impl Disk {
 
         // block current thread until disk ready
         c_println!("ch {} disk_done.p()", ch.name());
-        ch.disk_done.p();
+        if let Err(e) = self.wait_irq() {
+            panic!("identify {}: {}", self.name(), e);
+        }
 
         if !self.busy_wait(BUSY_WAITING_MILS) {
             panic!("wait on {} failed", self.name());
@@ -106,6 +144,17 @@ impl Disk {
         c_println!("cap = {} MB", buf.sectors() * 512 / 1024 / 1024);
     }
 
+    // block until interrupt of the channel, fail if it never comes
+    fn wait_irq(&self) -> Result<(), SE> {
+        let ch = self.ide();
+        if !ch.disk_done.p_timeout(BUSY_WAITING_MILS) {
+            ch.expecting = false;
+            println!("interrupt of {} timed out", self.name());
+            return Err("disk interrupt timed out");
+        }
+        Ok(())
+    }
+
     // wait until disk ready
     pub fn busy_wait(&self, mut mils: u32) -> bool {
         let ch = self.ide();
@@ -132,16 +181,54 @@ impl Disk {
         crate::asm::in_sw(ch.reg_data(), b);
     }
 
-    pub fn ide_read(&self, lba: u32, buf: &mut [u8], sec_n: usize) {
-        let cur = "init";
-        c_println!("ide read lba = {}, sec_n = {}", lba, sec_n);
+    // write n sections from buffer, require buf.len() >= sec_n * 512
+    pub fn write_secs(&self, buf: &[u8], sec_n: u8) {
+        let bytes = if sec_n == 0 { 256 * SEC_SIZE } else { sec_n as usize * SEC_SIZE };
+        let ch = self.ide();
+
+        assert!(buf.len() >= bytes, "size of buf {} < bytes {}", buf.len(), bytes);
+        let b = unsafe { core::slice::from_raw_parts(buf.as_ptr() as *const u16, bytes / 2) };
+        crate::asm::out_sw(ch.reg_data(), b);
+    }
+
+    pub fn ide_write(&self, lba: u32, buf: &[u8], sec_n: usize) -> Result<(), SE> {
+        assert!(lba < MAX_LBA, "lba {} overflow", lba);
+        assert!(buf.len() >= sec_n * SEC_SIZE, "buf.len() {} < sec_bytes {}", buf.len(), sec_n * SEC_SIZE);
+
+        let ch = self.ide();
+
+        let _gd = ch.lock.lock();
+        self.select();
+
+        let mut dones: usize = 0;
+
+        while dones < sec_n {
+            let todo = (sec_n - dones).min(256);
+
+            self.select_sec(lba + dones as u32, todo as u8);
+            ch.cmd_out(CMD_WRITE_SEC);
+
+            // the disk requests every sector by DRQ, and interrupts when it is written
+            for i in dones..dones + todo {
+                if !self.busy_wait(BUSY_WAITING_MILS) {
+                    return Err("disk not ready");
+                }
+                ch.expecting = true;
+                self.write_secs(&buf[i * SEC_SIZE..], 1);
+                self.wait_irq()?;
+            }
+            dones += todo;
+        }
+        Ok(())
+    }
+
+    pub fn ide_read(&self, lba: u32, buf: &mut [u8], sec_n: usize) -> Result<(), SE> {
         assert!(lba < MAX_LBA, "lba {} overflow", lba);
         assert!(buf.len() >= sec_n * SEC_SIZE, "buf.len() {} < sec_bytes {}", buf.len(), sec_n * SEC_SIZE);
 
         let ch = self.ide();
 
         let gd = ch.lock.lock();
-        c_println!("guard get");
         self.select();
 
         let mut dones: usize = 0;
@@ -152,21 +239,20 @@ impl Disk {
 
             self.select_sec(lba + dones as u32, todo as u8);
             ch.cmd_out(CMD_READ_SEC);
-            c_println!("cur {} ch {} done p()", cur, ch.name());
-            ch.disk_done.p();
-            c_println!("cur {} return from ch {} done p()", cur, ch.name());
 
-            if !self.busy_wait(BUSY_WAITING_MILS) {
-                panic!("busy wait failed for device {}", self.name());
+            // the disk interrupts once every sector is ready
+            for i in dones..dones + todo {
+                self.wait_irq()?;
+                if !self.busy_wait(BUSY_WAITING_MILS) {
+                    return Err("disk not ready");
+                }
+                // the next sector may interrupt as soon as this one is read
+                ch.expecting = i + 1 < dones + todo;
+                self.read_secs(&mut buf[i * SEC_SIZE..], 1);
             }
-
-            // read into buffer
-            self.read_secs(&mut buf[dones * SEC_SIZE..], todo as u8);
             dones += todo;
-            c_println!("dones = {} now ", dones);
         }
-
-        c_println!("read done");
+        Ok(())
     }
 
     fn select_sec(&self, lba: u32, sec_n: u8) {
@@ -185,7 +271,41 @@ impl Disk {
             *x = None;
         }
         let mut boot: [u8; SEC_SIZE] = [0u8; SEC_SIZE];
-        self.ide_read(0, &mut boot, 1);
+        if let Err(e) = self.ide_read(0, &mut boot, 1) {
+            println!("read boot sector of {}: {}", self.name(), e);
+            return;
+        }
+
+        use core::fmt::Write;
+        let disk = self as *const _ as usize;
+        let parts = partitions();
+
+        // extended partitions are not supported yet
+        for (i, e) in boot.partition_table().iter().enumerate() {
+            let (fs_type, start, sec_n) = (e.fs_type, e.start_lba, e.sec_n);
+            if fs_type == 0 || sec_n == 0 {
+                continue;
+            }
+
+            let mut name = [0u8; NAME_BUF_LEN];
+            write!(SliceWriter::new(&mut name), "{}{}", self.name(), i + 1);
+
+            self.primary_parts[i] = Some(Partition {
+                pointers: [0; 2],
+                start,
+                sec_n,
+                disk,
+                name,
+                sb: 0,
+                blocks: None,
+                inodes: None,
+                fs_type,
+            });
+
+            let p = self.primary_parts[i].as_mut().unwrap();
+            c_println!("partition {} type = 0x{:02X} start = {} sectors = {}", p.name(), fs_type, start, sec_n);
+            parts.append(p);
+        }
     }
 }
 
@@ -293,6 +413,7 @@ pub fn init() {
 
         // initialize lock
         ch.lock.init();
+        ch.lock.set_name(["ide-0", "ide-1"][ch_no]);
 
         // initialize the value as zero, call v() in interrupt handler
         ch.disk_done.value = 0;
@@ -323,10 +444,19 @@ pub fn init() {
     }
 }
 
+/// disks of initialized channels
+pub fn disks() -> impl Iterator<Item=&'static Disk> {
+    let ch_cnt = div_up!(crate::fs::disks() as usize, 2);
+    channels()[..ch_cnt].iter().flat_map(|x| x.devices.iter())
+}
+
+/// find the first partition with system id fs_type
+pub fn find_part(fs_type: u8) -> Option<&'static mut Partition> {
+    partitions().iter().find(|x| x.fs_type == fs_type)
+}
 
 pub fn int_handle(ctx: &'static mut IntCtx) {
     assert!(ctx.vec == 0x2e || ctx.vec == 0x2f, "ide::int_handle(): invalid vec");
-    c_println!("ide::int_handle: {}", ctx.vec);
     let ch_no = ctx.vec - 0x2e;
     let chs = channels();
     let ch = &mut chs[ch_no as usize];
@@ -337,14 +467,6 @@ pub fn int_handle(ctx: &'static mut IntCtx) {
     }
 
     ch.expecting = false;
-    c_println!("ch {} disk v()", ch.name());
     ch.disk_done.v();
-
-    let rd = crate::thread::data::ready();
-
-    c_println!("ready = ");
-    for p in rd.iter() {
-        c_println!("{} {} {:?}", p.name(), p.ticks, p.status);
-    }
     crate::asm::in_b(ch.reg_status());
 }
\ No newline at end of file
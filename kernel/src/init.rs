@@ -1,13 +1,33 @@
-use crate::thread::sync::Lock;
+use crate::println;
+use crate::thread::sync::Once;
+
+// console and memory locks are used after threads are initialized
+static LOCKS: Once = Once::new();
 
 pub fn init_locks() {
-    unsafe {
-        for x in [
-            (&mut crate::vga::VGA_LOCK_REF, &mut crate::vga::VGA_LOCK),
-            (&mut crate::mem::K_LOCK_REF, &mut crate::mem::K_LOCK),
-            (&mut crate::mem::U_LOCK_REF, &mut crate::mem::U_LOCK),
-        ] {
-           *x.0 =  Lock::new(x.1.as_ptr() as usize, x.1.len()) as *const _ as usize;
-        }
+    LOCKS.call_once(|| ());
+}
+
+pub fn locks_ready() -> bool {
+    LOCKS.is_completed()
+}
+
+/// path of the first user program, set MOS_INIT when building kernel to change it
+pub const INIT_PATH: &str = match option_env!("MOS_INIT") {
+    Some(x) => x,
+    None => "/sbin/init",
+};
+
+/// register programs of initrd and run INIT_PATH as pid 1
+/// called after drivers and swap are ready, panic if init cannot start
+pub fn start_user() {
+    let n = crate::thread::prog::load_initrd().unwrap();
+    println!("initrd: {} programs", n);
+    for (name, size) in crate::thread::prog::list() {
+        println!("  {} {} bytes", name, size);
+    }
+    match crate::thread::exec::spawn_init(INIT_PATH, &[INIT_PATH], &[]) {
+        Ok(pid) => println!("started {} as pid {}", INIT_PATH, pid),
+        Err(e) => panic!("cannot run {}: {}", INIT_PATH, e),
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,122 @@
+use crate::asm::{in_b, out_b};
+use crate::int::register;
+use crate::thread::reg::IntCtx;
+use crate::thread::sync::{IrqSpinLock, Semaphore};
+
+const KBD_VEC: u16 = 0x21;
+const COM1_VEC: u16 = 0x24;
+const KBD_DATA: u16 = 0x60;
+const COM1_PORT: u16 = 0x3f8;
+const BUF_SIZE: usize = 256;
+
+pub const BACKSPACE: u8 = 8;
+
+// scancodes of set 1
+const SC_EXTENDED: u8 = 0xe0;
+const SC_RELEASE: u8 = 0x80;
+const SC_L_SHIFT: u8 = 0x2a;
+const SC_R_SHIFT: u8 = 0x36;
+const SC_CTRL: u8 = 0x1d;
+const SC_CAPS: u8 = 0x3a;
+
+// characters of make codes 0x00..0x3a, 0 if not printable
+const KEYS: &[u8] = b"\0\x1b1234567890-=\x08\tqwertyuiop[]\n\0asdfghjkl;'`\0\\zxcvbnm,./\0*\0 ";
+const SHIFT_KEYS: &[u8] = b"\0\x1b!@#$%^&*()_+\x08\tQWERTYUIOP{}\n\0ASDFGHJKL:\"~\0|ZXCVBNM<>?\0*\0 ";
+
+/// characters typed on keyboard or received from com1, read by getc
+struct Input {
+    buf: [u8; BUF_SIZE],
+    head: usize,
+    tail: usize,
+    shift: bool,
+    ctrl: bool,
+    caps: bool,
+    extended: bool,
+}
+
+// shared by interrupt handlers and readers
+static INPUT: IrqSpinLock<Input> = IrqSpinLock::new(Input {
+    buf: [0; BUF_SIZE],
+    head: 0,
+    tail: 0,
+    shift: false,
+    ctrl: false,
+    caps: false,
+    extended: false,
+});
+// characters in buf
+static mut READY: Semaphore = Semaphore::new(0);
+
+pub fn init() {
+    register(KBD_VEC, kbd_handle);
+    register(COM1_VEC, com1_handle);
+    // interrupt when data available
+    out_b(COM1_PORT + 1, 0x01);
+}
+
+// called in interrupt, the character is dropped if buffer is full
+fn push(x: &mut Input, c: u8) {
+    let next = (x.tail + 1) % BUF_SIZE;
+    if next == x.head {
+        return;
+    }
+    x.buf[x.tail] = c;
+    x.tail = next;
+    unsafe { READY.v() };
+}
+
+/// block until a character is available
+pub fn getc() -> u8 {
+    unsafe { READY.p() };
+    let mut x = INPUT.lock();
+    let c = x.buf[x.head];
+    x.head = (x.head + 1) % BUF_SIZE;
+    c
+}
+
+fn kbd_handle(_: &'static mut IntCtx) {
+    let mut g = INPUT.lock();
+    let x = &mut *g;
+    let code = in_b(KBD_DATA);
+    if code == SC_EXTENDED {
+        x.extended = true;
+        return;
+    }
+    // keys of extended codes are not supported
+    if x.extended {
+        x.extended = false;
+        return;
+    }
+
+    let release = code & SC_RELEASE != 0;
+    match code & !SC_RELEASE {
+        SC_L_SHIFT | SC_R_SHIFT => x.shift = !release,
+        SC_CTRL => x.ctrl = !release,
+        SC_CAPS if !release => x.caps = !x.caps,
+        k if !release && (k as usize) < KEYS.len() => {
+            let mut c = if x.shift { SHIFT_KEYS[k as usize] } else { KEYS[k as usize] };
+            if x.caps && c.is_ascii_alphabetic() {
+                c ^= 0x20;
+            }
+            if x.ctrl && c.is_ascii_alphabetic() {
+                c = c.to_ascii_lowercase() - b'a' + 1;
+            }
+            if c != 0 {
+                push(x, c);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn com1_handle(_: &'static mut IntCtx) {
+    let mut x = INPUT.lock();
+    while in_b(COM1_PORT + 5) & 1 != 0 {
+        let c = match in_b(COM1_PORT) {
+            b'\r' => b'\n',
+            0x7f => BACKSPACE,
+            c => c,
+        };
+        push(&mut x, c);
+    }
+}
@@ -5,7 +5,11 @@ use crate::thread::reg::IntCtx;
 use crate::vga::{next_line, VGA_COL};
 
 const ENTRY_SIZE: usize = 0x2f + 1;
+pub const PF_VEC: usize = 0x0e;
 pub const SYS_VEC: usize = 0x80;
+// vectors of hardware interrupts from pic
+const IRQ_START: usize = 0x20;
+const IRQ_END: usize = 0x30;
 const E_FLAGS_IF: u32 = 0x00000200;
 
 // 32bit interrupt gate
@@ -15,6 +19,8 @@ const IDT_DESC_ATTR_DPL3: u8 = 1 << 7 | 3 << 5 | 0xe;
 static mut IDT_PTR: IdtPtr = IdtPtr { size: 0, off: 0 };
 static mut IDT: [u64; SYS_VEC + 1] = [0; SYS_VEC + 1];
 static mut HANDLERS: [usize; SYS_VEC + 1] = [0; SYS_VEC + 1];
+// interrupts received by vector
+static mut COUNTS: [usize; SYS_VEC + 1] = [0; SYS_VEC + 1];
 
 
 pub static EXCEPTIONS: &[&'static str] = &[
@@ -52,26 +58,45 @@ extern "C" fn int_entry(esp: u32) {
     // IntCtx::debug(ctx as *const _);
     // println!("m\n");
     // crate::vga::next_line();
-    if vec < 20 {
-        c_println!("EXCEPTION: {}", EXCEPTIONS[vec as usize]);
-        loop {}
-    }
-
     if (vec as usize) > SYS_VEC{
         return;
     }
 
     unsafe {
+        COUNTS[vec as usize] += 1;
         let f = HANDLERS[vec as usize];
+
+        // exception without handler is fatal
+        if vec < 20 && f == 0 {
+            c_println!("EXCEPTION: {}", EXCEPTIONS[vec as usize]);
+            loop {}
+        }
+
         if f == 0 {
             return;
         }
 
         let f: fn(ctx: &'static mut IntCtx) = core::mem::transmute(f);
+
+        // exceptions and syscalls run in context of current thread and may sleep
+        let irq = (IRQ_START..IRQ_END).contains(&(vec as usize));
+        let cur = current_pcb();
+        if irq {
+            cur.irq_depth += 1;
+        }
         f(ctx);
+        // the handler may switch to other threads, cur is running again here
+        if irq {
+            cur.irq_depth -= 1;
+        }
     }
 }
 
+/// whether running a handler of hardware interrupt
+pub fn in_interrupt() -> bool {
+    current_pcb().irq_depth != 0
+}
+
 pub fn int_enabled() -> bool {
     let e_flags = crate::e_flags!();
     e_flags & E_FLAGS_IF != 0
@@ -83,6 +108,11 @@ pub fn register(vec: u16, handle: fn(int_ctx: &'static mut IntCtx)) {
     }
 }
 
+/// number of interrupts received by each vector
+pub fn counts() -> &'static [usize] {
+    unsafe { &COUNTS }
+}
+
 fn idt() -> &'static mut [GateBits] {
     unsafe {
         let pp = IDT.as_ptr() as usize;
@@ -141,7 +171,8 @@ fn init_pic() {
     out_b(PIC_S_DATA, 0x02);
     out_b(PIC_S_DATA, 0x01);
 
-    out_b(PIC_M_DATA, 0xf8);
+    // timer, keyboard, cascade and com1
+    out_b(PIC_M_DATA, 0xe8);
     out_b(PIC_S_DATA, 0xbf);
 }
 
@@ -10,9 +10,6 @@
 
 use core::panic::PanicInfo;
 
-use rlib::sys::{call_0, write};
-use rlib::sys::NR::{GET_PID, WRITE};
-
 use crate::mem::alloc::v2p;
 use crate::mem::arena::{free, malloc};
 use crate::mem::page::OS_MEM_OFF;
@@ -20,8 +17,6 @@ use crate::mem::Pool;
 use crate::thread::Status;
 use crate::thread::sync::{block, sleep_mils};
 
-pub const S_LOCK_SZ: usize = 1024;
-
 macro_rules! cst {
     ($p: expr) => {
         unsafe { &mut *($p as *mut _) }
@@ -62,6 +57,8 @@ mod timer;
 mod vga;
 mod sys;
 mod fs;
+mod input;
+mod monitor;
 
 
 /// The name **must be** `_start`, otherwise the compiler doesn't output anything
@@ -82,6 +79,9 @@ pub extern "C" fn _start() {
         // load interrupt descriptor table
         int::init();
 
+        // demand paging for user process
+        crate::mem::fault::init();
+
 
         // add main thread into list, register scheduler
         crate::thread::init();
@@ -89,7 +89,6 @@ pub extern "C" fn _start() {
 
         // increase interrupt frequency
         crate::timer::init();
-        // crate::thread::user::create(th_print_d, 15, "th0", 0xff);
 
         // initialize syscall
         crate::sys::init();
@@ -112,34 +111,20 @@ pub extern "C" fn _start() {
 
         c_println!("disks = {}", crate::fs::disks());
         crate::fs::ide::init();
+        crate::mem::swap::init();
 
 
+        // debug shell on console and com1
+        crate::monitor::init();
+
+        // boot into userland, main thread has nothing left to do
+        crate::init::start_user();
         loop {
-            // let lk = crate::asm::lock();
-            // lk.lock();
-            println!("hello from init thread");
-            // lk.unlock();
-            sleep_mils(1000);
+            block(Status::Hanging);
         }
     }
 }
 
-extern "C" fn th_print_d(d: usize) {
-    loop {
-        let msg = "hello world from user space\n";
-        let p = rlib::sys::malloc(msg.len());
-        let slice: &'static mut[u8] = unsafe {
-            core::slice::from_raw_parts_mut(p as *mut _, msg.len())
-        };
-
-        slice[..msg.len()].copy_from_slice(msg.as_bytes());
-
-        rlib::sys::write(slice.as_ptr(), msg.len());
-
-        rlib::sys::free(p);
-    }
-}
-
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
     crate::int::disable_int();
@@ -2,11 +2,12 @@ use rlib::bitmap::Bitmap;
 
 use crate::{OS_MEM_OFF, println};
 use crate::err::SE;
+use crate::mem::{frame, stat};
 use crate::mem::{
     fill_zero, k_lock, kernel_pool, PAGE_SIZE, PagePool, u_lock, user_pool, v_pool, VPool,
 };
-use crate::mem::page::{DEFAULT_PT_ATTR, LOOP_BACK_PD, map_page, page_table, PDE_START, RESERVED_MEM, USER_P_START, USER_V_START, VirtualAddress};
-use crate::thread::current_pcb;
+use crate::mem::page::{DEFAULT_PT_ATTR, KERNEL_PT_ATTR, large_page_size, loop_back_pd, map_page, page_dir, PageTableEntry, PDE_START, pe_size, PG_NX, pt_window, pte, RESERVED_MEM, USER_P_START, USER_V_START, VirtualAddress};
+use crate::thread::{current_pcb, PCB};
 
 pub trait VAlloc {
     /// try to alloc continuous pages in virtual memory space
@@ -24,6 +25,9 @@ pub trait PAlloc {
     /// try to alloc one page in physical memory space, not required to be continuous
     fn p_alloc(&mut self) -> Result<usize, SE>;
 
+    /// try to alloc physically continuous pages
+    fn p_alloc_n(&mut self, pages: usize) -> Result<usize, SE>;
+
     fn remove(&mut self, off: usize);
 }
 
@@ -43,27 +47,37 @@ impl VAlloc for VPool {
     }
 
     fn free(&mut self, off: usize, pages: usize) {
-        let p = v2p(off);
-        let phy = if p >= USER_P_START { user_pool() }  else { kernel_pool() };
-
         for i in 0..pages {
             let v = off + i * PAGE_SIZE;
-            let p = v2p(v);
-            phy.remove(p);
 
-            // remove pte
-            let pte: *mut u32 = pte_ptr(v) as *mut _;
-            let p = pte as usize;
+            // reserved page may not be backed by physical memory
+            let e = match pte(v) {
+                Some(e) if e.exists() => e,
+                Some(e) => {
+                    if let Some(slot) = e.swap_slot() {
+                        crate::mem::swap::put(slot);
+                        e.clear();
+                    }
+                    continue;
+                }
+                None => continue,
+            };
+
+            let p = v2p(v);
+            let user = p >= USER_P_START;
+            let phy = if user { user_pool() } else { kernel_pool() };
 
-            // flush page table
-            unsafe {
-                *pte = *pte & !1;
-                asm!("invlpg [{}]", in(reg) p);
+            // shared user frame is freed by its last mapping
+            if !user || frame::put(p) {
+                phy.remove(p);
+                phy.avl_pages += 1;
             }
 
+            // remove pte, flush page table
+            e.delete();
+            crate::asm::invlpg(v);
         }
 
-        phy.avl_pages += pages;
         self.remove(off, pages);
     }
 
@@ -75,19 +89,36 @@ impl VAlloc for VPool {
 
 impl PAlloc for PagePool {
     fn p_alloc(&mut self) -> Result<usize, SE> {
-        self.avl_pages -= 1;
         let bit_i = self.bitmap.try_alloc(1);
         if bit_i < 0 {
             return Err("memory overflow");
         }
+        self.avl_pages -= 1;
         self.bitmap.set(bit_i as usize, true);
 
         let p = self.p_start + (bit_i as usize) * PAGE_SIZE;
+        stat::set(p, self.owner());
         // return physical address of this page
         Ok(p)
     }
 
+    fn p_alloc_n(&mut self, pages: usize) -> Result<usize, SE> {
+        let bit_i = self.bitmap.try_alloc(pages);
+        if bit_i < 0 {
+            return Err("memory overflow");
+        }
+        self.avl_pages -= pages;
+        self.bitmap.fill_n(bit_i as usize, pages, true);
+
+        let p = self.p_start + (bit_i as usize) * PAGE_SIZE;
+        for i in 0..pages {
+            stat::set(p + i * PAGE_SIZE, self.owner());
+        }
+        Ok(p)
+    }
+
     fn remove(&mut self, off: usize) {
+        stat::clear(off);
         self.bitmap.set((off - self.p_start) / PAGE_SIZE, false);
     }
 }
@@ -98,18 +129,52 @@ pub enum Pool {
     USER,
 }
 
+// kernel pages are hidden from user mode
+fn attr(p: &Pool) -> u16 {
+    if *p == Pool::KERNEL { KERNEL_PT_ATTR } else { DEFAULT_PT_ATTR }
+}
+
 pub fn pte_ptr(v: usize) -> *const u8 {
     // get address of page table entry by loopback
-    let x: usize = 0xffc00000 | v.pde_i() << 12 | v.pte_i() * 4;
+    let x: usize = pt_window() + (v >> 12) * pe_size();
     x as *const _
 }
 
 pub fn v2p(v: usize) -> usize {
-    // get address of page table entry by loopback
-    let x: usize = 0xffc00000 | v.pde_i() << 12 | v.pte_i() * 4;
-    let y: *const usize = x as *const _;
-    // physical address of page table
-    unsafe { *y & 0xfffff000 | v & 0xfff }
+    let pde = &page_dir(loop_back_pd())[v.pde_i()];
+    if pde.large() {
+        return pde.addr() | v & (large_page_size() - 1);
+    }
+
+    // get page table entry by loopback
+    let e: &PageTableEntry = cst!(pte_ptr(v) as usize);
+    e.addr() | v & 0xfff
+}
+
+/// allocate a frame in user pool, pages of user processes are swapped out if the pool is exhausted
+/// caller must hold u_lock
+pub fn user_frame() -> Result<usize, SE> {
+    loop {
+        if let Ok(p) = user_pool().p_alloc() {
+            return Ok(p);
+        }
+        if !crate::mem::swap::swap_out() {
+            return Err("memory overflow");
+        }
+    }
+}
+
+/// mark pages [start, start + pages * PAGE_SIZE) of user process as used without physical memory
+/// the pages are filled on first touch by page fault handler
+pub fn reserve(pcb: &mut PCB, start: usize, pages: usize) -> Result<(), SE> {
+    let v = pcb.v_pool();
+    let bit_i = (start - v.v_start) / PAGE_SIZE;
+
+    if (bit_i..bit_i + pages).any(|i| v.bitmap.test(i)) {
+        return Err("virtual address in use");
+    }
+    v.bitmap.fill_n(bit_i, pages, true);
+    Ok(())
 }
 
 // allocate only one page by virtual address
@@ -146,10 +211,11 @@ pub fn alloc_one(p: Pool, v_ad: usize, init: bool) -> Result<usize, SE> {
     } else {
         user_pool()
     };
+    let flags = attr(&p) | PG_NX;
     v.bitmap.set(bit_i, true);
     let p = pp.p_alloc()?;
 
-    map_page(pd, v_ad, p, DEFAULT_PT_ATTR, false, true)?;
+    map_page(pd, v_ad, p, flags, false, true)?;
 
     if init {
         fill_zero(v_ad, PAGE_SIZE);
@@ -157,6 +223,13 @@ pub fn alloc_one(p: Pool, v_ad: usize, init: bool) -> Result<usize, SE> {
     Ok(v_ad)
 }
 
+/// free kernel pages allocated by pg_alloc(Pool::KERNEL, ..)
+pub fn pg_free(v: usize, pages: usize) {
+    let lk = k_lock();
+    let _gd = lk.map(|x| x.lock());
+    v_pool().free(v, pages);
+}
+
 pub fn pg_alloc(p: Pool, pages: usize, init: bool) -> Result<usize, SE> {
     let lk = if p == Pool::KERNEL {
         k_lock()
@@ -194,7 +267,7 @@ pub fn pg_alloc(p: Pool, pages: usize, init: bool) -> Result<usize, SE> {
             pd,
             v_start + i * PAGE_SIZE,
             p_a,
-            DEFAULT_PT_ATTR,
+            attr(&p) | PG_NX,
             false,
             true,
         )?;
@@ -4,7 +4,7 @@ use rlib::link::{LinkedList, Node};
 use crate::{c_println, Pool, println};
 use crate::mem::{fill_zero, k_lock, PAGE_SIZE, pg_alloc, u_lock, v_pool};
 use crate::mem::alloc::VAlloc;
-use crate::thread::current_pcb;
+use crate::thread::{current_pcb, PCB_SIZE};
 
 pub const DESC_CNT: usize = 7;
 pub const MAX_BLK_SIZE: usize = 1024;
@@ -85,13 +85,22 @@ pub fn init() {
     init_descs(k_descs());
 }
 
-// malloc memory in kernel space
+// malloc memory in address space of current thread
 pub fn malloc(size: usize) -> usize {
+    malloc_in(current_pcb().user(), size)
+}
+
+// malloc memory in kernel space even if current thread is a user process
+pub fn k_malloc(size: usize) -> usize {
+    malloc_in(false, size)
+}
+
+fn malloc_in(user: bool, size: usize) -> usize {
     let cur = current_pcb();
 
-    let l = if cur.user() { u_lock() } else { k_lock() };
-    let pool = if cur.user() { Pool::USER } else { Pool::KERNEL };
-    let ds = if cur.user() { &mut cur.desc } else { k_descs() };
+    let l = if user { u_lock() } else { k_lock() };
+    let pool = if user { Pool::USER } else { Pool::KERNEL };
+    let ds = if user { &mut cur.desc } else { k_descs() };
     let _gd = l.map(|x| x.lock());
 
     // allocate page by page if size > 1024
@@ -131,21 +140,36 @@ pub fn malloc(size: usize) -> usize {
 }
 
 pub fn free(p: usize) {
+    free_in(current_pcb().user(), p)
+}
+
+// free memory allocated by k_malloc
+pub fn k_free(p: usize) {
+    free_in(false, p)
+}
+
+fn free_in(user: bool, p: usize) {
     let cur = current_pcb();
-    let lk = if cur.user() { u_lock() } else { k_lock() };
+    let lk = if user { u_lock() } else { k_lock() };
     let _gd = lk.map(|x| x.lock());
 
     let b: &'static mut Blk = cst!(p);
     b.pointers.fill(0);
     let a = b.arena();
+    let owner = cur.off();
 
-    let v_p = if cur.user() { cur.v_pool() } else { v_pool() };
+    let v_p = if user { cur.v_pool() } else { v_pool() };
 
     if a.large {
         v_p.free(a as *const _ as usize, a.count);
         return;
     }
 
+    // block allocated by parent before fork, the free list belongs to parent
+    if user && a.desc / PCB_SIZE * PCB_SIZE != owner {
+        return;
+    }
+
     // collect free block
     let d = a.desc().unwrap();
     d.frees.append(b);
@@ -0,0 +1,146 @@
+use rlib::alloc_static;
+
+use crate::{c_println, println};
+use crate::err::SE;
+use crate::int::{PF_VEC, register};
+use crate::mem::{fill_zero, frame, PAGE_SIZE, shm, swap, u_lock, user_pool};
+use crate::mem::alloc::{PAlloc, reserve, user_frame};
+use crate::mem::page::{kmap, kunmap, map_page, OS_MEM_OFF, PG_RW, pte};
+use crate::mem::vma::{self, Backing, Vma, VM_GROWS_DOWN};
+use crate::thread::{current_pcb, PCB};
+use crate::thread::reg::IntCtx;
+use crate::thread::exit::{SEGV_EXIT, thread_exit};
+
+// bits of page fault error code
+pub const PF_PRESENT: u32 = 1;
+pub const PF_WRITE: u32 = 1 << 1;
+pub const PF_USER: u32 = 1 << 2;
+
+/// default limit of user stack size, like RLIMIT_STACK
+pub const DEFAULT_STACK_LIMIT: usize = 8 << 20;
+
+// bounce buffer for copy on write, protected by u_lock
+alloc_static!(COW_BUF, cow_buf, [u8; PAGE_SIZE]);
+
+pub fn init() {
+    register(PF_VEC as u16, page_fault);
+}
+
+fn page_fault(ctx: &'static mut IntCtx) {
+    let v = crate::asm::cr2();
+    let code = ctx.e_code;
+    let eip = ctx.eip;
+    let cur = current_pcb();
+
+    if !cur.user() || v >= OS_MEM_OFF {
+        panic!("page fault in kernel, addr = 0x{:08X} eip = 0x{:08X} code = {}", v, eip, code);
+    }
+
+    // the page exists, copy on write or protection violation
+    if code & PF_PRESENT != 0 {
+        let page = v & !(PAGE_SIZE - 1);
+        match vma::find(cur, v) {
+            Some(x) if code & PF_WRITE != 0 && x.writable() => {
+                if cow(x, page).is_err() {
+                    println!("{}: out of memory at 0x{:08X}", cur.name(), v);
+                    segv(cur, v, eip);
+                }
+            }
+            _ => segv(cur, v, eip),
+        }
+        return;
+    }
+
+    let vma = match vma::find(cur, v).or_else(|| grow_stack(cur, v)) {
+        Some(x) => x,
+        None => return segv(cur, v, eip),
+    };
+
+    if vma.inaccessible() || (code & PF_WRITE != 0 && !vma.writable()) {
+        return segv(cur, v, eip);
+    }
+
+    let page = v & !(PAGE_SIZE - 1);
+    let swapped = pte(page).map(|e| e.swap_slot().is_some()).unwrap_or(false);
+    let r = match vma.backing {
+        Backing::Anon if swapped => swap::swap_in(vma, page),
+        Backing::Anon => fault_in(cur, vma, page),
+        Backing::Shm(..) => shm::fault_in(cur, vma, page),
+    };
+
+    if let Err(e) = r {
+        println!("{}: {} at 0x{:08X}", cur.name(), e, v);
+        segv(cur, v, eip);
+    }
+}
+
+/// allocate a zeroed physical page for virtual address v of current process
+fn fault_in(pcb: &mut PCB, vma: &Vma, v: usize) -> Result<(), SE> {
+    let lk = u_lock();
+    let _gd = lk.map(|x| x.lock());
+
+    let p = user_frame()?;
+    // zero through kmap, kernel writes respect read only pages
+    let k = kmap(p);
+    fill_zero(k, PAGE_SIZE);
+    kunmap(k);
+    if let Err(e) = map_page(pcb.pd, v, p, vma.pte_flags(), false, true) {
+        let up = user_pool();
+        up.remove(p);
+        up.avl_pages += 1;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// give current process a private copy of the write protected page v
+fn cow(vma: &Vma, v: usize) -> Result<(), SE> {
+    let lk = u_lock();
+    let _gd = lk.map(|x| x.lock());
+
+    let e = pte(v).unwrap();
+    let p = e.addr();
+
+    // the last mapping owns the frame
+    if !frame::shared(p) {
+        e.set(PG_RW, true);
+        crate::asm::invlpg(v);
+        return Ok(());
+    }
+
+    let np = user_frame()?;
+    let buf = cow_buf();
+    let page = unsafe { core::slice::from_raw_parts_mut(v as *mut u8, PAGE_SIZE) };
+    buf.copy_from_slice(page);
+
+    frame::put(p);
+    e.write(np, vma.pte_flags());
+    crate::asm::invlpg(v);
+    page.copy_from_slice(buf);
+    Ok(())
+}
+
+/// extend the stack area downward if v is just below it and the limit is not exceeded
+fn grow_stack(pcb: &mut PCB, v: usize) -> Option<&'static mut Vma> {
+    let st = pcb.vmas_ref().iter().find(|x| x.flags & VM_GROWS_DOWN != 0 && x.start > v)?;
+    let start = v & !(PAGE_SIZE - 1);
+
+    if st.end - start > pcb.stack_limit {
+        return None;
+    }
+
+    // never grow into another area
+    if vma::find_overlap(pcb, start, st.start).is_some() {
+        return None;
+    }
+
+    reserve(pcb, start, (st.start - start) / PAGE_SIZE).ok()?;
+    st.start = start;
+    Some(st)
+}
+
+fn segv(pcb: &mut PCB, v: usize, eip: u32) {
+    c_println!("segmentation fault: {} addr = 0x{:08X} eip = 0x{:08X}", pcb.name(), v, eip);
+    vma::dump(pcb);
+    thread_exit(SEGV_EXIT);
+}
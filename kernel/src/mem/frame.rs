@@ -0,0 +1,53 @@
+use rlib::div_up;
+
+use crate::err::SE;
+use crate::mem::{PAGE_SIZE, user_pool};
+use crate::mem::page::static_alloc;
+
+/// reference count of physical frames in user pool, one byte per frame
+/// 0 means the frame is mapped once, n means it is shared by n + 1 mappings
+static mut REFS: usize = 0;
+static mut FRAMES: usize = 0;
+
+fn refs() -> &'static mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(REFS as *mut _, FRAMES) }
+}
+
+#[inline]
+fn index(p: usize) -> usize {
+    let u = user_pool();
+    assert!(p >= u.p_start, "0x{:08X} is not a user frame", p);
+    (p - u.p_start) / PAGE_SIZE
+}
+
+// called before page setup
+pub fn init(frames: usize) {
+    unsafe {
+        REFS = static_alloc(div_up!(frames, PAGE_SIZE), true).unwrap();
+        FRAMES = frames;
+    }
+}
+
+/// add a mapping to frame p
+pub fn share(p: usize) -> Result<(), SE> {
+    let r = &mut refs()[index(p)];
+    if *r == u8::MAX {
+        return Err("too many references of frame");
+    }
+    *r += 1;
+    Ok(())
+}
+
+/// drop a mapping of frame p, return true if no mapping left and the frame should be freed
+pub fn put(p: usize) -> bool {
+    let r = &mut refs()[index(p)];
+    if *r == 0 {
+        return true;
+    }
+    *r -= 1;
+    false
+}
+
+pub fn shared(p: usize) -> bool {
+    refs()[index(p)] != 0
+}
@@ -0,0 +1,180 @@
+use rlib::div_up;
+
+use crate::err::SE;
+use crate::mem::{frame, PAGE_SIZE, u_lock};
+use crate::mem::alloc::{reserve, VAlloc};
+use crate::mem::page::{OS_MEM_OFF, pte, PG_NX, PG_RW, PG_US, USER_V_START};
+use crate::mem::vma::{self, Vma, VM_EXEC, VM_READ, VM_SHARED, VM_WRITE, VmaKind};
+use crate::thread::PCB;
+
+const PROT_MASK: u32 = VM_READ | VM_WRITE | VM_EXEC;
+
+#[inline]
+fn page_up(x: usize) -> usize {
+    div_up!(x, PAGE_SIZE) * PAGE_SIZE
+}
+
+fn check_range(addr: usize, len: usize) -> Result<usize, SE> {
+    if addr % PAGE_SIZE != 0 || len == 0 {
+        return Err("invalid range");
+    }
+    let end = addr.checked_add(page_up(len)).ok_or("invalid range")?;
+    if addr < USER_V_START || end > OS_MEM_OFF {
+        return Err("invalid range");
+    }
+    Ok(end)
+}
+
+/// set program break of pcb to addr, return the new break
+/// return current break if addr is 0 or the request cannot be satisfied
+/// the heap is [page_up(brk_start), page_up(brk)), it may be split by mprotect
+pub fn brk(pcb: &mut PCB, addr: usize) -> usize {
+    if addr < pcb.brk_start || addr >= OS_MEM_OFF {
+        return pcb.brk;
+    }
+
+    let start = page_up(pcb.brk_start);
+    let old_end = page_up(pcb.brk);
+    let new_end = page_up(addr);
+
+    if new_end > old_end {
+        // the heap never grows into another area
+        if vma::find_overlap(pcb, old_end, new_end).is_some() {
+            return pcb.brk;
+        }
+        if reserve(pcb, old_end, (new_end - old_end) / PAGE_SIZE).is_err() {
+            return pcb.brk;
+        }
+        // the last piece of the heap ends at old_end
+        let top = if old_end > start { vma::find(pcb, old_end - 1) } else { None };
+        match top.filter(|x| x.kind == VmaKind::Heap && x.end == old_end) {
+            Some(h) => h.end = new_end,
+            None => {
+                if vma::add(pcb, old_end, new_end, VM_READ | VM_WRITE, VmaKind::Heap).is_err() {
+                    pcb.v_pool().remove(old_end, (new_end - old_end) / PAGE_SIZE);
+                    return pcb.brk;
+                }
+            }
+        }
+    }
+
+    if new_end < old_end {
+        for x in pcb.vmas_ref().iter().filter(|x| x.kind == VmaKind::Heap) {
+            match clip(pcb, x, new_end, old_end) {
+                Ok(Some(x)) => {
+                    unmap_pages(pcb, x.start, x.pages());
+                    vma::remove(pcb, x);
+                }
+                Ok(None) => {}
+                Err(_) => return pcb.brk,
+            }
+        }
+    }
+
+    pcb.brk = addr;
+    addr
+}
+
+/// map anonymous memory with protection prot, pages are allocated on first touch
+/// addr is a hint, the kernel picks an address if it is 0 or not available
+pub fn mmap(pcb: &mut PCB, addr: usize, len: usize, prot: u32) -> Result<usize, SE> {
+    if len == 0 {
+        return Err("invalid range");
+    }
+    let pages = div_up!(len, PAGE_SIZE);
+
+    let fixed = check_range(addr, len).is_ok()
+        && vma::find_overlap(pcb, addr, addr + pages * PAGE_SIZE).is_none()
+        && reserve(pcb, addr, pages).is_ok();
+
+    let start = if fixed { addr } else { pcb.v_pool().v_alloc(pages)? };
+
+    if let Err(e) = vma::add(pcb, start, start + pages * PAGE_SIZE, prot & PROT_MASK, VmaKind::Mmap) {
+        pcb.v_pool().remove(start, pages);
+        return Err(e);
+    }
+    Ok(start)
+}
+
+/// remove mappings in [addr, addr + len), physical pages are freed
+pub fn munmap(pcb: &mut PCB, addr: usize, len: usize) -> Result<(), SE> {
+    let end = check_range(addr, len)?;
+
+    // shared memory is unmapped by shm detach
+    if pcb.vmas_ref().iter().any(|x| x.kind == VmaKind::Shm && x.overlaps(addr, end)) {
+        return Err("shm area");
+    }
+    // the heap is shrunk by brk, which keeps pcb.brk in step
+    if pcb.vmas_ref().iter().any(|x| x.kind == VmaKind::Heap && x.overlaps(addr, end)) {
+        return Err("heap area");
+    }
+
+    for x in pcb.vmas_ref().iter() {
+        if let Some(x) = clip(pcb, x, addr, end)? {
+            unmap_pages(pcb, x.start, x.pages());
+            vma::remove(pcb, x);
+        }
+    }
+    Ok(())
+}
+
+/// change protection of pages in [addr, addr + len)
+pub fn mprotect(pcb: &mut PCB, addr: usize, len: usize, prot: u32) -> Result<(), SE> {
+    let end = check_range(addr, len)?;
+    let mut v = addr;
+
+    // every page in range must be mapped
+    while v < end {
+        let x = vma::find(pcb, v).ok_or("address not mapped")?;
+        v = x.end;
+    }
+
+    for x in pcb.vmas_ref().iter() {
+        if let Some(x) = clip(pcb, x, addr, end)? {
+            x.flags = (x.flags & !PROT_MASK) | (prot & PROT_MASK);
+            protect_pages(x);
+        }
+    }
+    Ok(())
+}
+
+/// split x so that the returned area is exactly the part of x in [start, end)
+fn clip(pcb: &mut PCB, x: &'static mut Vma, start: usize, end: usize) -> Result<Option<&'static mut Vma>, SE> {
+    if !x.overlaps(start, end) {
+        return Ok(None);
+    }
+    if x.start < start {
+        vma::split(pcb, x, start)?;
+    }
+    if x.end > end {
+        return Ok(Some(vma::split(pcb, x, end)?));
+    }
+    Ok(Some(x))
+}
+
+// free physical pages and virtual address of current address space
+fn unmap_pages(pcb: &mut PCB, start: usize, pages: usize) {
+    let lk = u_lock();
+    let _gd = lk.map(|x| x.lock());
+    pcb.v_pool().free(start, pages);
+}
+
+// apply protection of the area to pages already mapped
+fn protect_pages(x: &Vma) {
+    let lk = u_lock();
+    let _gd = lk.map(|x| x.lock());
+    for i in 0..x.pages() {
+        let v = x.start + i * PAGE_SIZE;
+        if let Some(e) = pte(v) {
+            if !e.exists() {
+                continue;
+            }
+            // a private frame shared after fork stays read only, the first write copies it
+            let cow = x.flags & VM_SHARED == 0 && frame::shared(e.addr());
+            e.set(PG_RW, x.writable() && !cow);
+            e.set(PG_US, !x.inaccessible());
+            e.set(PG_NX, !x.executable());
+            crate::asm::invlpg(v);
+        }
+    }
+}
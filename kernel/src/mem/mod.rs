@@ -1,37 +1,32 @@
-pub use {alloc::pg_alloc, alloc::Pool, page::init_page, page::page_enabled, page::PageTable, page::PT_LEN};
+pub use {alloc::pg_alloc, alloc::pg_free, alloc::Pool, page::init_page, page::page_enabled, page::PageTable};
 use rlib::bitmap::Bitmap;
 use rlib::size_of;
 
 use crate::{asm, println};
-use crate::mem::page::{PDE_START, PT_SIZE, RESERVED_MEM, static_alloc, USER_P_START};
-use crate::S_LOCK_SZ;
+use crate::mem::page::{KMAP_PAGES, KMAP_START, PDE_START, RESERVED_MEM, static_alloc, USER_P_START, VMALLOC_END, VMALLOC_START};
 use crate::thread::sync::Lock;
 
 pub mod alloc;
 pub mod page;
 pub mod arena;
-
-pub static mut K_LOCK: [u8; S_LOCK_SZ] = [0u8; S_LOCK_SZ];
-pub static mut K_LOCK_REF: usize = 0;
-
-pub static mut U_LOCK: [u8; S_LOCK_SZ] = [0u8; S_LOCK_SZ];
-pub static mut U_LOCK_REF: usize = 0;
-
+pub mod fault;
+pub mod frame;
+pub mod mmap;
+pub mod shm;
+pub mod stat;
+pub mod swap;
+pub mod vma;
+pub mod vmalloc;
+
+static mut K_LOCK: Lock = Lock::named("k_lock");
+static mut U_LOCK: Lock = Lock::named("u_lock");
 
 pub fn k_lock() -> Option<&'static mut Lock> {
-    if unsafe { K_LOCK_REF == 0 } {
-        None
-    } else {
-        Some(cst!(K_LOCK_REF))
-    }
+    crate::init::locks_ready().then(|| unsafe { &mut K_LOCK })
 }
 
 pub fn u_lock() -> Option<&'static mut Lock> {
-    if unsafe { U_LOCK_REF == 0 } {
-        None
-    } else {
-        Some(cst!(U_LOCK_REF))
-    }
+    crate::init::locks_ready().then(|| unsafe { &mut U_LOCK })
 }
 
 const KERNEL_MEM: usize = 3 << 20;
@@ -71,6 +66,11 @@ impl PagePool {
     pub fn size(&self) -> usize {
         self.total_pages * PAGE_SIZE
     }
+
+    // owner of pages allocated from this pool unless changed by stat::set
+    pub fn owner(&self) -> stat::Owner {
+        if self.p_start >= USER_P_START { stat::Owner::User } else { stat::Owner::KernelHeap }
+    }
 }
 
 // kernel physical memory pool
@@ -89,6 +89,11 @@ fn v_pool() -> &'static mut VPool {
     cast!(VPool, BUF_ST_SIZE * 2)
 }
 
+// virtual memory pool of vmalloc window
+fn vmalloc_pool() -> &'static mut VPool {
+    cast!(VPool, BUF_ST_SIZE * 3)
+}
+
 fn bit_map() -> &'static mut [u8] {
     unsafe {
         if BIT_MAP == 0 {
@@ -135,6 +140,59 @@ pub fn debug() {
         u.p_start / 1024 / 1024,
         u.bitmap.len()
     );
+    for o in stat::OWNERS.iter() {
+        println!("{:11} : {} pages", o.name(), stat::count(*o));
+    }
+    for a in stat::arena(arena::k_descs()).iter() {
+        println!("arena {:4} : {} blocks per page, {} free", a.blk_sz, a.blocks, a.frees);
+    }
+    for r in regions().iter() {
+        println!(
+            "{:7} : 0x{:08X}-0x{:08X} used {}K of {}K",
+            r.name,
+            r.start,
+            r.end,
+            r.used_pages * PAGE_SIZE / 1024,
+            (r.end - r.start) / 1024
+        );
+    }
+}
+
+/// usage of a kernel virtual memory region
+pub struct Region {
+    pub name: &'static str,
+    pub start: usize,
+    pub end: usize,
+    pub used_pages: usize,
+}
+
+fn used_pages(v: &VPool, pages: usize) -> usize {
+    (0..pages).filter(|i| v.bitmap.test(*i)).count()
+}
+
+/// regions of kernel half of address space
+pub fn regions() -> [Region; 3] {
+    let k_start = page::OS_MEM_OFF + RESERVED_MEM;
+    [
+        Region {
+            name: "kernel",
+            start: k_start,
+            end: k_start + KERNEL_MEM,
+            used_pages: used_pages(v_pool(), KERNEL_MEM / PAGE_SIZE),
+        },
+        Region {
+            name: "vmalloc",
+            start: VMALLOC_START,
+            end: VMALLOC_END,
+            used_pages: used_pages(vmalloc_pool(), (VMALLOC_END - VMALLOC_START) / PAGE_SIZE),
+        },
+        Region {
+            name: "kmap",
+            start: KMAP_START,
+            end: KMAP_START + KMAP_PAGES * PAGE_SIZE,
+            used_pages: page::kmap_used(),
+        },
+    ]
 }
 
 pub fn init() {
@@ -174,9 +232,15 @@ pub fn init() {
     u.bitmap = alloc_bit_map(user_pages / 8);
     u.total_pages = user_pages;
     u.avl_pages = u.total_pages;
+    frame::init(user_pages);
+    stat::init(kernel_pages + user_pages);
 
     v.bitmap = alloc_bit_map(kernel_pages / 8);
     v.v_start = page::OS_MEM_OFF + RESERVED_MEM;
 
+    let vm = vmalloc_pool();
+    vm.bitmap = alloc_bit_map((VMALLOC_END - VMALLOC_START) / PAGE_SIZE / 8);
+    vm.v_start = VMALLOC_START;
+
     arena::init();
 }
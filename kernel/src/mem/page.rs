@@ -1,23 +1,109 @@
+use core::ops::{Index, IndexMut};
+
 use crate::{c_println, println};
 use crate::asm::KERNEL_ENTRY;
 use crate::err::SE;
-use crate::mem::{fill_zero, KERNEL_MEM, kernel_pool, PAGE_SIZE};
-use crate::mem::alloc::PAlloc;
+use crate::mem::{fill_zero, k_lock, KERNEL_MEM, kernel_pool, PAGE_SIZE, vmalloc_pool};
+use crate::mem::alloc::{PAlloc, VAlloc};
+use crate::mem::stat::{self, Owner};
+use crate::thread::data::all;
 use crate::thread::{MAIN_PRIORITY, PCB, PCB_PAGES, PCB_SIZE, Routine, Status};
 
-pub const PE_SIZE: usize = 4;
-pub const PT_LEN: usize = 1024;
-pub const PT_SIZE: usize = PE_SIZE * PT_LEN;
+// pae is selected at boot if cpuid reports it, entries are 64 bits then
+// the low 32 bits of a pae entry have the same layout, since physical memory is below 4GB
+static mut PAE: bool = false;
+// size of the largest page directory, followed by the page directory pointer table of pae
+const MAX_PD_PAGES: usize = 5;
+
+/// whether page tables have 64 bits pae entries
+pub fn pae() -> bool {
+    unsafe { PAE }
+}
+
+pub fn pe_size() -> usize {
+    if pae() { 8 } else { 4 }
+}
+
+/// entries of a page table
+pub fn pt_len() -> usize {
+    PAGE_SIZE / pe_size()
+}
+
+// a page directory entry maps 4MB, or 2MB with pae
+fn pde_shift() -> usize {
+    if pae() { 21 } else { 22 }
+}
+
+/// page directory entries of whole address space
+/// the four page directories of pae are physically continuous and indexed as one
+pub fn pd_len() -> usize {
+    1 << (32 - pde_shift())
+}
+
+pub fn pd_size() -> usize {
+    pd_len() * pe_size()
+}
+
+/// pages of a page directory, with the page directory pointer table of pae
+pub fn pd_pages() -> usize {
+    if pae() { pd_size() / PAGE_SIZE + 1 } else { 1 }
+}
+
 pub const OS_MEM_OFF: usize = 0xc0000000;
 pub const RESERVED_MEM: usize = 5 << 20;
 pub const USER_P_START: usize = 8 << 20;
 pub const USER_V_START: usize = 8 << 20;
+// initial program break of user process
+pub const USER_BRK_START: usize = 1 << 30;
 pub const DEFAULT_PT_ATTR: u16 = 7;
+// kernel pages are not accessible in user mode
+pub const KERNEL_PT_ATTR: u16 = PG_P | PG_RW;
+
+// bits of page table entry
+pub const PG_P: u16 = 1;
+pub const PG_RW: u16 = 1 << 1;
+pub const PG_US: u16 = 1 << 2;
+// page level write through and cache disable
+pub const PG_PWT: u16 = 1 << 3;
+pub const PG_PCD: u16 = 1 << 4;
+// set by cpu when the page is accessed
+pub const PG_A: u16 = 1 << 5;
+// page directory entry maps a large page
+pub const PG_PS: u16 = 1 << 7;
+// available to os, the page is not present and saved in swap slot data >> 12
+pub const PG_SWAP: u16 = 1 << 9;
+// available to os, translated to execute disable bit 63 when nx is enabled
+pub const PG_NX: u16 = 1 << 11;
+// bit 63 of pae entry, in the high 32 bits
+const NX_HIGH: u32 = 1 << 31;
+// physical address in entry
+const ADDR_MASK: u32 = 0xfffff000;
+
+// window of vmalloc, pages in it are not physically continuous
+pub const VMALLOC_START: usize = OS_MEM_OFF + (16 << 20);
+pub const VMALLOC_END: usize = KMAP_START;
+
+// window for temporary mappings of physical pages outside the kernel pool
+pub const KMAP_START: usize = 0xff400000;
+pub const KMAP_PAGES: usize = 32;
+static mut KMAP_USED: u32 = 0;
+
+pub fn large_page_size() -> usize {
+    1 << pde_shift()
+}
+
+// feature bits of cpuid leaf 1 edx
+const CPUID_PSE: u32 = 1 << 3;
+const CPUID_PAE: u32 = 1 << 6;
+// feature bit of cpuid leaf 0x80000001 edx
+const CPUID_NX: u32 = 1 << 20;
+static mut PSE: bool = false;
+static mut NX: bool = false;
 
 // 1m area for page
 pub const PAGE_AREA_SIZE: usize = 1024 * 1024;
 
-// page directory must align to 4K
+// page directory of kernel, must align to 4K
 pub const PDE_START: usize = 0x10000;
 
 pub const BUF_UPPER_BOUND: usize = 0x80000;
@@ -25,11 +111,59 @@ pub const BUF_UPPER_BOUND: usize = 0x80000;
 // for static alloc before page setup
 static mut PD_USED: usize = 0;
 
-pub const LOOP_BACK_PD: usize = 0xfffff000;
+// page tables of current address space are visible in this window by loopback entries
+// the page directory itself is the last page table in it
+pub fn pt_window() -> usize {
+    if pae() { 0xff800000 } else { 0xffc00000 }
+}
 
+pub fn loop_back_pd() -> usize {
+    if pae() { 0xffffc000 } else { 0xfffff000 }
+}
 
 pub fn page_table(off: usize) -> PageTable {
-    unsafe { core::slice::from_raw_parts_mut(off as *mut _, PT_LEN) }
+    PageTable { off, len: pt_len() }
+}
+
+pub fn page_dir(off: usize) -> PageTable {
+    PageTable { off, len: pd_len() }
+}
+
+/// value of cr3 for page directory pd
+pub fn cr3(pd: usize) -> usize {
+    if pae() { pd + pd_size() } else { pd }
+}
+
+// point loopback entries to the page directory pd itself, and fill page directory pointer table of pae
+fn init_pd(pd: usize) {
+    let mut d = page_dir(pd);
+    for (k, i) in (pt_window().pde_i()..pd_len()).enumerate() {
+        d[i].write(pd + k * PAGE_SIZE, KERNEL_PT_ATTR);
+    }
+
+    // only present bit is allowed in pdpte
+    if pae() {
+        let mut t = page_table(cr3(pd));
+        for k in 0..pd_size() / PAGE_SIZE {
+            t[k].write(pd + k * PAGE_SIZE, PG_P);
+        }
+    }
+}
+
+/// create page directory of a process, the kernel half is shared with PDE_START
+/// the pages are physically continuous in kernel pool, which is identity mapped
+pub fn new_pd() -> Result<usize, SE> {
+    let lk = k_lock();
+    let _gd = lk.map(|x| x.lock());
+
+    let pd = kernel_pool().p_alloc_n(pd_pages())?;
+    for i in 0..pd_pages() {
+        stat::set(pd + i * PAGE_SIZE, Owner::PageTable);
+    }
+    fill_zero(pd, pd_pages() * PAGE_SIZE);
+    page_dir(pd).copy_from(&page_dir(PDE_START));
+    init_pd(pd);
+    Ok(pd)
 }
 
 pub trait VirtualAddress {
@@ -40,31 +174,99 @@ pub trait VirtualAddress {
 impl VirtualAddress for usize {
     #[inline]
     fn pde_i(self) -> usize {
-        self >> 22
+        self >> pde_shift()
     }
 
     #[inline]
     fn pte_i(self) -> usize {
-        (self >> 12) & 0x3ff
+        (self >> 12) & (pt_len() - 1)
+    }
+}
+
+/// page table or directory at kernel address off, entries are pe_size() bytes apart
+#[derive(Clone, Copy)]
+pub struct PageTable {
+    off: usize,
+    len: usize,
+}
+
+impl PageTable {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    // kernel address of the first entry
+    pub fn off(&self) -> usize {
+        self.off
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&'static mut PageTableEntry> {
+        let off = self.off;
+        (0..self.len).map(move |i| cst!(off + i * pe_size()))
+    }
+
+    /// copy all entries of src
+    pub fn copy_from(&self, src: &PageTable) {
+        assert_eq!(self.len, src.len);
+        unsafe { core::ptr::copy_nonoverlapping(src.off as *const u8, self.off as *mut u8, self.len * pe_size()) };
     }
 }
 
-pub type PageTable = &'static mut [PageTableEntry];
+impl Index<usize> for PageTable {
+    type Output = PageTableEntry;
 
+    fn index(&self, i: usize) -> &PageTableEntry {
+        assert!(i < self.len, "entry {} out of page table", i);
+        cst!(self.off + i * pe_size())
+    }
+}
+
+impl IndexMut<usize> for PageTable {
+    fn index_mut(&mut self, i: usize) -> &mut PageTableEntry {
+        assert!(i < self.len, "entry {} out of page table", i);
+        cst!(self.off + i * pe_size())
+    }
+}
+
+/// low 32 bits of an entry in a page table, the high 32 bits of pae follow it
+/// an entry is only accessed in place, since a copy loses the high bits
 #[repr(transparent)]
-#[derive(Clone, Copy)]
 pub struct PageTableEntry {
-    pub data: usize,
+    pub data: u32,
+}
+
+// bits of flags in the low 32 bits, PG_NX is moved to bit 63 or dropped if nx is disabled
+fn bits(flags: u16) -> u32 {
+    (flags & !PG_NX) as u32
 }
 
 impl PageTableEntry {
-    fn zero() -> Self {
-        PageTableEntry { data: 0 }
+    // high 32 bits of pae entry
+    fn high(&self) -> Option<&mut u32> {
+        if pae() { Some(cst!(self as *const _ as usize + 4)) } else { None }
     }
 
-    pub fn new(p_start: usize, flags: u16) -> PageTableEntry {
-        Self {
-            data: p_start | (flags as usize),
+    /// replace the entry by page or page table p with flags
+    pub fn write(&mut self, p: usize, flags: u16) {
+        self.data = p as u32 | bits(flags);
+        if let Some(h) = self.high() {
+            *h = if flags & PG_NX != 0 && nx() { NX_HIGH } else { 0 };
+        }
+    }
+
+    /// copy entry src, including the high bits of pae
+    pub fn copy(&mut self, src: &PageTableEntry) {
+        self.data = src.data;
+        if let (Some(h), Some(s)) = (self.high(), src.high()) {
+            *h = *s;
+        }
+    }
+
+    /// remove the entry
+    pub fn clear(&mut self) {
+        self.data = 0;
+        if let Some(h) = self.high() {
+            *h = 0;
         }
     }
 
@@ -72,19 +274,173 @@ impl PageTableEntry {
         (self.data & 1) != 0
     }
 
+    // page directory entry of a large page
+    pub fn large(&self) -> bool {
+        self.exists() && self.has(PG_PS)
+    }
+
+    pub fn has(&self, flags: u16) -> bool {
+        let nx = flags & PG_NX != 0 && nx() && self.high().map(|h| *h & NX_HIGH != 0).unwrap_or(false);
+        nx || self.data & bits(flags) != 0
+    }
+
+    // physical address of the page or the page table
+    pub fn addr(&self) -> usize {
+        (self.data & ADDR_MASK) as usize
+    }
+
+    pub fn set(&mut self, flags: u16, v: bool) {
+        if v {
+            self.data |= bits(flags);
+        } else {
+            self.data &= !bits(flags);
+        }
+        if flags & PG_NX != 0 && nx() {
+            let h = self.high().unwrap();
+            if v { *h |= NX_HIGH } else { *h &= !NX_HIGH }
+        }
+    }
+
+    /// turn the entry into a page saved in swap slot
+    pub fn write_swapped(&mut self, slot: usize) {
+        self.clear();
+        self.data = (slot << 12) as u32 | bits(PG_SWAP);
+    }
+
+    pub fn swap_slot(&self) -> Option<usize> {
+        if self.exists() || !self.has(PG_SWAP) {
+            return None;
+        }
+        Some((self.data >> 12) as usize)
+    }
+
     pub fn delete(&mut self) {
         self.data = self.data & (!1)
     }
 
     pub fn sub_table(&self) -> PageTable {
-        let p = self.data & 0xfffff000;
-        unsafe { core::slice::from_raw_parts_mut(p as *mut _, PT_LEN) }
+        page_table(self.addr())
+    }
+}
+
+/// page table entry of v in current address space, None if the page table not exists
+pub fn pte(v: usize) -> Option<&'static mut PageTableEntry> {
+    let pd = page_dir(loop_back_pd());
+    if !pd[v.pde_i()].exists() || pd[v.pde_i()].large() {
+        return None;
+    }
+    Some(cst!(crate::mem::alloc::pte_ptr(v) as usize))
+}
+
+/// whether v is mapped in current address space
+pub fn mapped(v: usize) -> bool {
+    let pde = &page_dir(loop_back_pd())[v.pde_i()];
+    if pde.large() {
+        return pde.exists();
+    }
+    pte(v).map(|x| x.exists()).unwrap_or(false)
+}
+
+/// map physical page p into the kmap window, return the virtual address
+pub fn kmap(p: usize) -> usize {
+    let old = crate::int::disable_int();
+    let used = unsafe { &mut KMAP_USED };
+    let i = (0..KMAP_PAGES).find(|i| *used & 1 << i == 0).expect("kmap window exhausted");
+    *used |= 1 << i;
+    crate::int::set_int(old);
+
+    let v = KMAP_START + i * PAGE_SIZE;
+    pte(v).unwrap().write(p & !(PAGE_SIZE - 1), PG_P | PG_RW | PG_NX);
+    crate::asm::invlpg(v);
+    v
+}
+
+pub fn kunmap(v: usize) {
+    let i = (v - KMAP_START) / PAGE_SIZE;
+    pte(v).unwrap().clear();
+    crate::asm::invlpg(v);
+
+    let old = crate::int::disable_int();
+    unsafe { KMAP_USED &= !(1 << i) };
+    crate::int::set_int(old);
+}
+
+pub fn kmap_used() -> usize {
+    unsafe { KMAP_USED.count_ones() as usize }
+}
+
+/// cache policy of a mapping
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum CacheMode {
+    WriteBack,
+    WriteThrough,
+    // for device registers
+    Uncached,
+}
+
+impl CacheMode {
+    fn flags(&self) -> u16 {
+        match self {
+            CacheMode::WriteBack => 0,
+            CacheMode::WriteThrough => PG_PWT,
+            CacheMode::Uncached => PG_PCD | PG_PWT,
+        }
+    }
+}
+
+/// map physical range [phys, phys + len) of a device into the vmalloc window
+/// no frame of physical pools is consumed, return virtual address of phys
+pub fn ioremap(phys: usize, len: usize, mode: CacheMode) -> Result<usize, SE> {
+    if len == 0 || phys.checked_add(len).is_none() {
+        return Err("invalid range");
+    }
+    let off = phys & (PAGE_SIZE - 1);
+    let base = phys - off;
+    let pages = (off + len + PAGE_SIZE - 1) / PAGE_SIZE;
+
+    let lk = k_lock();
+    let _gd = lk.map(|x| x.lock());
+
+    // an unmapped guard page follows the area like vmalloc
+    let v = vmalloc_pool().v_alloc(pages + 1)?;
+    for i in 0..pages {
+        let r = map_page(PDE_START, v + i * PAGE_SIZE, base + i * PAGE_SIZE, PG_P | PG_RW | PG_NX | mode.flags(), false, true);
+        if let Err(e) = r {
+            unmap_io(v, i);
+            return Err(e);
+        }
+    }
+    Ok(v + off)
+}
+
+/// remove mapping created by ioremap, addr is the address returned by it
+pub fn iounmap(addr: usize) {
+    assert!(addr >= VMALLOC_START && addr < VMALLOC_END, "0x{:08X} is not mapped by ioremap", addr);
+    let base = addr & !(PAGE_SIZE - 1);
+
+    let lk = k_lock();
+    let _gd = lk.map(|x| x.lock());
+
+    let mut pages = 0;
+    while pte(base + pages * PAGE_SIZE).map(|e| e.exists()).unwrap_or(false) {
+        pages += 1;
+    }
+    unmap_io(base, pages);
+}
+
+// remove pte of device pages, the physical memory is not owned by kernel
+fn unmap_io(v: usize, pages: usize) {
+    for i in 0..pages {
+        let a = v + i * PAGE_SIZE;
+        pte(a).unwrap().clear();
+        crate::asm::invlpg(a);
     }
+    vmalloc_pool().remove(v, pages + 1);
 }
 
 // allocate pages before setup page
 pub fn static_alloc(pages: usize, init: bool) -> Result<usize, SE> {
-    let off = unsafe { PDE_START + PT_SIZE + PD_USED * PT_SIZE };
+    let off = unsafe { PDE_START + (MAX_PD_PAGES + PD_USED) * PAGE_SIZE };
     let avl = (BUF_UPPER_BOUND - off) / PAGE_SIZE;
     if avl < pages {
         return Err("overflow");
@@ -100,11 +456,84 @@ pub fn static_alloc(pages: usize, init: bool) -> Result<usize, SE> {
     Ok(off)
 }
 
-// map
+/// whether 4MB pages are supported by cpu
+pub fn pse() -> bool {
+    unsafe { PSE }
+}
+
+/// whether execute disable bit is enabled, only with pae
+pub fn nx() -> bool {
+    unsafe { NX }
+}
+
+/// map [v, v + len) to [p, p + len) with the same flags
+/// aligned 4MB chunks are mapped by large pages if supported, the rest by 4KB pages
+/// callers split the range where permissions differ
+pub fn map_range(pd: usize, v: usize, p: usize, len: usize, flags: u16, alloc: bool) -> Result<(), SE> {
+    let mut off = 0;
+    while off < len {
+        let (va, pa) = (v + off, p + off);
+        let large = pse()
+            && va % large_page_size() == 0
+            && pa % large_page_size() == 0
+            && len - off >= large_page_size()
+            && !page_dir(pd)[va.pde_i()].exists();
+
+        if large {
+            map_page(pd, va, pa, flags | PG_PS, false, alloc)?;
+            off += large_page_size();
+        } else {
+            map_page(pd, va, pa, flags, false, alloc)?;
+            off += PAGE_SIZE;
+        }
+    }
+    Ok(())
+}
+
+// map, a 4MB page is mapped if PG_PS in flags
+/// map user page v to p in page directory pd, which need not be the current one
+/// page tables of user space are in kernel pool, which is identity mapped
+pub fn map_user_page(pd: usize, v: usize, p: usize, flags: u16) -> Result<(), SE> {
+    assert!(v < OS_MEM_OFF, "0x{:08X} is not in user space", v);
+    let lk = k_lock();
+    let _gd = lk.map(|x| x.lock());
+
+    let mut pd = page_dir(pd);
+    let i = v.pde_i();
+    if !pd[i].exists() {
+        let pt = kernel_pool().p_alloc()?;
+        stat::set(pt, Owner::PageTable);
+        fill_zero(pt, PAGE_SIZE);
+        pd[i].write(pt, DEFAULT_PT_ATTR);
+    }
+    pd[i].sub_table()[v.pte_i()].write(p, flags);
+    Ok(())
+}
+
 pub fn map_page(pd: usize, v: usize, p: usize, flags: u16, trace: bool, alloc: bool) -> Result<(), SE> {
-    let pd = page_table(pd);
+    let mut pd = page_dir(pd);
     let pde_i = v.pde_i();
 
+    if flags & PG_PS != 0 {
+        assert!(pse(), "large page not supported");
+        assert!(
+            v % large_page_size() == 0 && p % large_page_size() == 0,
+            "0x{:08X} -> 0x{:08X} not aligned to large page", v, p
+        );
+        if pd[pde_i].exists() {
+            return Err("page table already exists");
+        }
+        pd[pde_i].write(p, flags);
+        if alloc && v >= OS_MEM_OFF {
+            sync_kernel_pde(pde_i, &pd[pde_i]);
+        }
+        return Ok(());
+    }
+
+    if pd[pde_i].large() {
+        return Err("mapped by large page");
+    }
+
     if trace {
         println!("map 0x{:08X} to 0x{:08X}", v, p);
         println!("pd {} exists = {}", pde_i, pd[pde_i].exists());
@@ -113,30 +542,38 @@ pub fn map_page(pd: usize, v: usize, p: usize, flags: u16, trace: bool, alloc: b
     if !pd[pde_i].exists() {
         let k = kernel_pool();
         let buf = if alloc {
-            k.p_alloc()?
+            let b = k.p_alloc()?;
+            stat::set(b, Owner::PageTable);
+            b
         } else {
             static_alloc(1, true)?
         };
         if trace {
             println!("create buf 0x{:08X}", buf);
         }
-        pd[pde_i] = PageTableEntry::new(buf, flags);
+        // permissions are controlled by page table entries
+        pd[pde_i].write(buf, DEFAULT_PT_ATTR);
+
+        // page tables of kernel half are shared by all processes
+        if alloc && v >= OS_MEM_OFF {
+            sync_kernel_pde(pde_i, &pd[pde_i]);
+        }
 
         if alloc {
-            fill_zero((PT_LEN - 1) << 22 | pde_i << 12, PAGE_SIZE);
+            fill_zero(pt_window() + pde_i * PAGE_SIZE, PAGE_SIZE);
         }
     }
 
-    let pt = if alloc {
+    let mut pt = if alloc {
         // access physical memory by loopback
-        page_table((PT_LEN - 1) << 22 | pde_i << 12)
+        page_table(pt_window() + pde_i * PAGE_SIZE)
     } else { pd[pde_i].sub_table() };
 
     if trace {
         println!("pte i = {}", v.pte_i());
         println!(
             "sub table physical address = :{:08X}",
-            pd[pde_i].sub_table().as_ptr() as usize
+            pd[pde_i].addr()
         );
     }
     if pt[v.pte_i()].exists() {
@@ -149,10 +586,23 @@ pub fn map_page(pd: usize, v: usize, p: usize, flags: u16, trace: bool, alloc: b
         );
     }
 
-    pt[v.pte_i()] = PageTableEntry::new(p, flags);
+    pt[v.pte_i()].write(p, flags);
     Ok(())
 }
 
+// copy a page directory entry of kernel half to every address space
+fn sync_kernel_pde(pde_i: usize, e: &PageTableEntry) {
+    page_dir(PDE_START)[pde_i].copy(e);
+    for p in all().iter().filter(|p| p.pd != 0) {
+        page_dir(p.pd)[pde_i].copy(e);
+    }
+}
+
+extern "C" {
+    // end of kernel code, defined in link.ld
+    static _text_end: u8;
+}
+
 static mut PAGE_ENABLED: bool = false;
 
 pub fn page_enabled() -> &'static mut bool {
@@ -160,21 +610,43 @@ pub fn page_enabled() -> &'static mut bool {
 }
 
 pub fn init_page() {
+    // paging is not enabled yet, bits of CR4 and EFER take effect with CR0.PG
+    // the entry format is chosen before any page table is built
+    let features = crate::asm::cpuid(1).3;
+    if features & CPUID_PAE != 0 {
+        unsafe { PAE = true };
+        crate::asm::enable_pae();
+
+        // large pages of pae are always supported
+        unsafe { PSE = true };
+        if crate::asm::cpuid(0x80000000).0 >= 0x80000001 && crate::asm::cpuid(0x80000001).3 & CPUID_NX != 0 {
+            unsafe { NX = true };
+            crate::asm::enable_nx();
+        }
+    } else if features & CPUID_PSE != 0 {
+        unsafe { PSE = true };
+        crate::asm::enable_pse();
+    }
+
     // init bitmaps
     crate::mem::init();
 
-    fill_zero(PDE_START, PT_SIZE);
+    fill_zero(PDE_START, MAX_PD_PAGES * PAGE_SIZE);
 
-    for i in 0..(RESERVED_MEM + KERNEL_MEM) / PAGE_SIZE {
-        map_page(PDE_START, i * PAGE_SIZE, i * PAGE_SIZE, DEFAULT_PT_ATTR, false, false).unwrap();
-    }
-    for i in 0..RESERVED_MEM / PAGE_SIZE {
-        map_page(PDE_START, OS_MEM_OFF + i * PAGE_SIZE, i * PAGE_SIZE, DEFAULT_PT_ATTR, false, false).unwrap();
-    }
+    // kernel image and direct map, only the loader and kernel code below text end are executable
+    let text_end = unsafe { &_text_end as *const u8 as usize + PAGE_SIZE - 1 } & !(PAGE_SIZE - 1);
+    let kernel_end = RESERVED_MEM + KERNEL_MEM;
+    map_range(PDE_START, 0, 0, text_end, KERNEL_PT_ATTR, false).unwrap();
+    map_range(PDE_START, text_end, text_end, kernel_end - text_end, KERNEL_PT_ATTR | PG_NX, false).unwrap();
+    map_range(PDE_START, OS_MEM_OFF, 0, RESERVED_MEM, KERNEL_PT_ATTR | PG_NX, false).unwrap();
+
+    let mut pd = page_dir(PDE_START);
+
+    // page table of kmap window is shared by all page directories
+    pd[KMAP_START.pde_i()].write(static_alloc(1, true).unwrap(), DEFAULT_PT_ATTR);
 
     // loopback page directory
-    let pd = page_table(PDE_START);
-    pd[PT_LEN - 1] = PageTableEntry::new(PDE_START, DEFAULT_PT_ATTR);
+    init_pd(PDE_START);
 
     let init_off = static_alloc(PCB_PAGES, true).unwrap();
     // init process
@@ -191,5 +663,5 @@ pub fn init_page() {
 
     println!("new stack = 0x{:08X}", new_stack);
     // println!("new stack");
-    crate::asm::page_jmp(PDE_START, new_stack, KERNEL_ENTRY);
+    crate::asm::page_jmp(cr3(PDE_START), new_stack, KERNEL_ENTRY);
 }
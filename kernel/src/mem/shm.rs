@@ -0,0 +1,216 @@
+use rlib::{alloc_static, div_up, size_of};
+
+use crate::err::SE;
+use crate::mem::{fill_zero, frame, PAGE_SIZE, Pool, u_lock, user_pool};
+use crate::mem::alloc::{PAlloc, pg_alloc, pg_free, reserve, user_frame, VAlloc};
+use crate::mem::page::{kmap, kunmap, map_page, OS_MEM_OFF, USER_V_START};
+use crate::mem::vma::{self, Backing, Vma, VM_READ, VM_SHARED, VM_WRITE, VmaKind};
+use crate::thread::PCB;
+
+pub const MAX_SHM: usize = 32;
+
+/// shared memory segment, frames are allocated on first touch and owned by the segment
+/// every mapping of a frame adds a reference by frame::share
+pub struct Shm {
+    used: bool,
+    key: u32,
+    pages: usize,
+    // kernel address of [usize; pages], physical address of frames, 0 if not allocated
+    frames: usize,
+    // number of address spaces this segment is attached to
+    attaches: usize,
+    // unlinked, open cannot find it by key any more
+    removed: bool,
+}
+
+impl Shm {
+    fn frames(&self) -> &'static mut [usize] {
+        unsafe { core::slice::from_raw_parts_mut(self.frames as *mut _, self.pages) }
+    }
+}
+
+alloc_static!(SEGMENTS, segments, [Shm; MAX_SHM]);
+
+fn get(id: usize) -> Result<&'static mut Shm, SE> {
+    if id >= MAX_SHM || !segments()[id].used {
+        return Err("invalid shm id");
+    }
+    Ok(&mut segments()[id])
+}
+
+/// get id of the segment with key, create it with size bytes if not exists
+pub fn open(key: u32, size: usize) -> Result<usize, SE> {
+    let lk = u_lock();
+    let _gd = lk.map(|x| x.lock());
+    // div_up! overflows for sizes near usize::MAX
+    let pages = size / PAGE_SIZE + (size % PAGE_SIZE != 0) as usize;
+
+    let segs = segments();
+    if let Some(i) = (0..MAX_SHM).find(|i| segs[*i].used && !segs[*i].removed && segs[*i].key == key) {
+        if segs[i].pages < pages {
+            return Err("shm too small");
+        }
+        return Ok(i);
+    }
+
+    if pages == 0 {
+        return Err("invalid shm size");
+    }
+    // a segment larger than user memory can never be filled
+    if pages > user_pool().total_pages {
+        return Err("shm too large");
+    }
+
+    let i = (0..MAX_SHM).find(|i| !segs[*i].used).ok_or("too many shm")?;
+    let s = &mut segs[i];
+    let bytes = pages * size_of!(usize);
+    s.frames = pg_alloc(Pool::KERNEL, div_up!(bytes, PAGE_SIZE), false)?;
+    fill_zero(s.frames, bytes);
+    s.used = true;
+    s.key = key;
+    s.pages = pages;
+    s.attaches = 0;
+    s.removed = false;
+    Ok(i)
+}
+
+/// remove the name key, the segment is destroyed now if not attached, or on last detach
+pub fn unlink(key: u32) -> Result<(), SE> {
+    let lk = u_lock();
+    let _gd = lk.map(|x| x.lock());
+
+    let segs = segments();
+    let i = (0..MAX_SHM)
+        .find(|i| segs[*i].used && !segs[*i].removed && segs[*i].key == key)
+        .ok_or("shm not found")?;
+    let s = &mut segs[i];
+    s.removed = true;
+    if s.attaches == 0 {
+        destroy(s);
+    }
+    Ok(())
+}
+
+/// map segment id into address space of pcb at addr, the kernel picks an address if addr is 0 or not available
+pub fn attach(pcb: &mut PCB, id: usize, addr: usize) -> Result<usize, SE> {
+    let lk = u_lock();
+    let _gd = lk.map(|x| x.lock());
+    let s = get(id)?;
+    let len = s.pages * PAGE_SIZE;
+
+    let fixed = addr % PAGE_SIZE == 0
+        && addr >= USER_V_START
+        && addr.checked_add(len).map(|x| x <= OS_MEM_OFF).unwrap_or(false)
+        && vma::find_overlap(pcb, addr, addr + len).is_none()
+        && reserve(pcb, addr, s.pages).is_ok();
+
+    let start = if fixed { addr } else { pcb.v_pool().v_alloc(s.pages)? };
+
+    match vma::add(pcb, start, start + len, VM_READ | VM_WRITE | VM_SHARED, VmaKind::Shm) {
+        Ok(x) => x.backing = Backing::Shm(id, start),
+        Err(e) => {
+            pcb.v_pool().remove(start, s.pages);
+            return Err(e);
+        }
+    }
+    s.attaches += 1;
+    Ok(start)
+}
+
+/// unmap the segment attached at addr, the segment is destroyed on last detach
+pub fn detach(pcb: &mut PCB, addr: usize) -> Result<(), SE> {
+    let lk = u_lock();
+    let _gd = lk.map(|x| x.lock());
+
+    let id = match vma::find(pcb, addr).map(|x| x.backing) {
+        Some(Backing::Shm(id, base)) if base == addr => id,
+        _ => return Err("shm not attached"),
+    };
+
+    // the area may be split by mprotect
+    for x in pcb.vmas_ref().iter() {
+        if x.backing == Backing::Shm(id, addr) {
+            pcb.v_pool().free(x.start, x.pages());
+            vma::remove(pcb, x);
+        }
+    }
+
+    let s = get(id)?;
+    s.attaches -= 1;
+    if s.attaches == 0 {
+        destroy(s);
+    }
+    Ok(())
+}
+
+/// the segments attached by parent are attached by child of fork too
+pub fn dup(pcb: &PCB) {
+    let lk = u_lock();
+    let _gd = lk.map(|x| x.lock());
+
+    for x in pcb.vmas_ref().iter() {
+        if let Backing::Shm(id, base) = x.backing {
+            // count once for areas split by mprotect
+            if x.start == base {
+                segments()[id].attaches += 1;
+            }
+        }
+    }
+}
+
+/// drop segments attached by a dead process, its mappings are freed with the page tables
+pub fn release(pcb: &PCB) {
+    let lk = u_lock();
+    let _gd = lk.map(|x| x.lock());
+
+    for x in pcb.vmas_ref().iter() {
+        if let Backing::Shm(id, base) = x.backing {
+            let s = &mut segments()[id];
+            if x.start == base {
+                s.attaches -= 1;
+                if s.attaches == 0 {
+                    destroy(s);
+                }
+            }
+        }
+    }
+}
+
+fn destroy(s: &mut Shm) {
+    let up = user_pool();
+    for p in s.frames().iter() {
+        if *p != 0 && frame::put(*p) {
+            up.remove(*p);
+            up.avl_pages += 1;
+        }
+    }
+    pg_free(s.frames, div_up!(s.pages * size_of!(usize), PAGE_SIZE));
+    s.used = false;
+}
+
+/// map the frame of segment for virtual address v of current process, allocate it on first touch
+pub fn fault_in(pcb: &mut PCB, vma: &Vma, v: usize) -> Result<(), SE> {
+    let (id, base) = match vma.backing {
+        Backing::Shm(id, base) => (id, base),
+        _ => return Err("not shm"),
+    };
+
+    let lk = u_lock();
+    let _gd = lk.map(|x| x.lock());
+    let s = get(id)?;
+    let f = &mut s.frames()[(v - base) / PAGE_SIZE];
+    let flags = vma.pte_flags();
+
+    if *f == 0 {
+        *f = user_frame()?;
+        let k = kmap(*f);
+        fill_zero(k, PAGE_SIZE);
+        kunmap(k);
+    }
+    frame::share(*f)?;
+    if let Err(e) = map_page(pcb.pd, v, *f, flags, false, true) {
+        frame::put(*f);
+        return Err(e);
+    }
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use rlib::div_up;
+use rlib::sys::{ARENA_DESCS, ArenaInfo, MEM_OWNERS, MemInfo, OWNER_KERNEL_HEAP, OWNER_PAGE_TABLE, OWNER_PCB, OWNER_USER};
+
+use crate::mem::{kernel_pool, PAGE_SIZE, swap, user_pool};
+use crate::mem::arena::{BlkDesc, DESC_CNT, k_descs};
+use crate::mem::page::{OS_MEM_OFF, page_dir, RESERVED_MEM, static_alloc, USER_V_START, VirtualAddress};
+use crate::thread::PCB;
+
+const _: () = assert!(DESC_CNT == ARENA_DESCS);
+
+/// who a physical page is allocated for
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[repr(u8)]
+pub enum Owner {
+    KernelHeap = OWNER_KERNEL_HEAP as u8,
+    PageTable = OWNER_PAGE_TABLE as u8,
+    Pcb = OWNER_PCB as u8,
+    User = OWNER_USER as u8,
+}
+
+impl Owner {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Owner::KernelHeap => "kernel heap",
+            Owner::PageTable => "page table",
+            Owner::Pcb => "pcb",
+            Owner::User => "user",
+        }
+    }
+}
+
+pub const OWNERS: [Owner; MEM_OWNERS] = [Owner::KernelHeap, Owner::PageTable, Owner::Pcb, Owner::User];
+
+/// owner of every frame in kernel and user pool, one byte per frame
+/// 0 means free, otherwise owner + 1
+static mut TABLE: usize = 0;
+static mut FRAMES: usize = 0;
+static mut COUNTS: [usize; MEM_OWNERS] = [0; MEM_OWNERS];
+
+fn table() -> &'static mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(TABLE as *mut _, FRAMES) }
+}
+
+// called before page setup, frames of both pools are continuous from RESERVED_MEM
+pub fn init(frames: usize) {
+    unsafe {
+        TABLE = static_alloc(div_up!(frames, PAGE_SIZE), true).unwrap();
+        FRAMES = frames;
+    }
+}
+
+fn entry(p: usize) -> Option<&'static mut u8> {
+    if p < RESERVED_MEM {
+        return None;
+    }
+    table().get_mut((p - RESERVED_MEM) / PAGE_SIZE)
+}
+
+/// record frame p is used by owner o
+pub fn set(p: usize, o: Owner) {
+    let e = match entry(p) {
+        Some(e) => e,
+        None => return,
+    };
+    let counts = unsafe { &mut COUNTS };
+    if *e != 0 {
+        counts[*e as usize - 1] -= 1;
+    }
+    counts[o as usize] += 1;
+    *e = o as u8 + 1;
+}
+
+/// record frame p is freed
+pub fn clear(p: usize) {
+    if let Some(e) = entry(p) {
+        if *e != 0 {
+            unsafe { COUNTS[*e as usize - 1] -= 1 };
+            *e = 0;
+        }
+    }
+}
+
+/// pages allocated for owner o
+pub fn count(o: Owner) -> usize {
+    unsafe { COUNTS[o as usize] }
+}
+
+/// resident pages in user space of pcb, shared pages are counted by every mapping
+pub fn rss(pcb: &PCB) -> usize {
+    if pcb.pd == 0 {
+        return 0;
+    }
+    let pd = page_dir(pcb.pd);
+    (USER_V_START.pde_i()..OS_MEM_OFF.pde_i())
+        .filter(|i| pd[*i].exists())
+        .map(|i| pd[i].sub_table().iter().filter(|e| e.exists()).count())
+        .sum()
+}
+
+/// virtual pages of all areas of pcb
+pub fn vm(pcb: &PCB) -> usize {
+    pcb.vmas_ref().iter().map(|x| x.pages()).sum()
+}
+
+/// usage of arena descriptors, kernel ones or those of a user process
+pub fn arena(ds: &[BlkDesc]) -> [ArenaInfo; ARENA_DESCS] {
+    let mut r = [ArenaInfo::default(); ARENA_DESCS];
+    for (i, d) in ds.iter().enumerate() {
+        r[i].blk_sz = d.blk_sz as u32;
+        r[i].blocks = d.blocks as u32;
+        r[i].frees = d.frees.iter().count() as u32;
+    }
+    r
+}
+
+/// memory statistics seen by pcb
+pub fn report(pcb: &PCB) -> MemInfo {
+    let k = kernel_pool();
+    let u = user_pool();
+    let (swap_pages, swap_used) = swap::usage();
+
+    let mut m = MemInfo::default();
+    m.page_size = PAGE_SIZE as u32;
+    m.kernel_pages = k.total_pages as u32;
+    m.kernel_free = k.avl_pages as u32;
+    m.user_pages = u.total_pages as u32;
+    m.user_free = u.avl_pages as u32;
+    m.swap_pages = swap_pages as u32;
+    m.swap_used = swap_used as u32;
+    for o in OWNERS.iter() {
+        m.owners[*o as usize] = count(*o) as u32;
+    }
+    m.arena = arena(k_descs());
+    // descriptors of kernel threads are not initialized
+    if pcb.user() {
+        m.proc_arena = arena(&pcb.desc);
+    }
+    m.rss = rss(pcb) as u32;
+    m.vm = vm(pcb) as u32;
+    m
+}
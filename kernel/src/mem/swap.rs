@@ -0,0 +1,232 @@
+use rlib::{alloc_static, div_up};
+
+use crate::{c_println, println};
+use crate::err::SE;
+use crate::fs::ide::{find_part, Partition};
+use crate::mem::{PAGE_SIZE, Pool, u_lock, user_pool};
+use crate::mem::alloc::{PAlloc, pg_alloc, user_frame};
+use crate::mem::frame;
+use crate::mem::page::{kmap, kunmap, page_dir, PageTableEntry, pe_size, PG_A, pte, VirtualAddress};
+use crate::mem::vma::{self, Backing, Vma};
+use crate::thread::{current_pcb, PCB};
+use crate::thread::data::all;
+
+/// system id of linux swap partition
+pub const SWAP_FS_TYPE: u8 = 0x82;
+const SEC_SIZE: usize = 512;
+const SECS_PER_PAGE: usize = PAGE_SIZE / SEC_SIZE;
+
+/// swap device, a slot holds a page
+/// refs[i] is the number of page table entries refer to slot i, 0 means the slot is free
+pub struct SwapDev {
+    part: usize,
+    slots: usize,
+    refs: usize,
+    used: usize,
+}
+
+impl SwapDev {
+    fn part(&self) -> &'static mut Partition {
+        cst!(self.part)
+    }
+
+    fn refs(&self) -> &'static mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.refs as *mut _, self.slots) }
+    }
+
+    fn alloc(&mut self) -> Option<usize> {
+        let i = self.refs().iter().position(|x| *x == 0)?;
+        self.refs()[i] = 1;
+        self.used += 1;
+        Some(i)
+    }
+}
+
+alloc_static!(SWAP_DEV, swap_dev, SwapDev);
+
+// clock hand, the last evicted page as (pcb, virtual address)
+static mut HAND: (usize, usize) = (0, 0);
+
+fn dev() -> Option<&'static mut SwapDev> {
+    let d = swap_dev();
+    if d.part == 0 { None } else { Some(d) }
+}
+
+// called after ide initialized
+pub fn init() {
+    let part = match find_part(SWAP_FS_TYPE) {
+        Some(p) => p,
+        None => {
+            println!("no swap partition found");
+            return;
+        }
+    };
+
+    let slots = part.sectors() as usize / SECS_PER_PAGE;
+    if slots == 0 {
+        return;
+    }
+
+    let d = swap_dev();
+    d.refs = pg_alloc(Pool::KERNEL, div_up!(slots, PAGE_SIZE), true).unwrap();
+    d.slots = slots;
+    d.used = 0;
+    d.part = part as *const _ as usize;
+    c_println!("swap on {}, {} KB", part.name(), slots * PAGE_SIZE / 1024);
+}
+
+/// (total, used) slots of swap device
+pub fn usage() -> (usize, usize) {
+    dev().map(|d| (d.slots, d.used)).unwrap_or((0, 0))
+}
+
+/// another page table entry refers to slot, for fork
+pub fn dup(slot: usize) -> Result<(), SE> {
+    let r = &mut dev().unwrap().refs()[slot];
+    if *r == u8::MAX {
+        return Err("too many references of swap slot");
+    }
+    *r += 1;
+    Ok(())
+}
+
+/// drop a reference of slot, the slot is free when no entry refers to it
+pub fn put(slot: usize) {
+    let d = dev().unwrap();
+    let r = &mut d.refs()[slot];
+    assert!(*r > 0, "swap slot {} is free", slot);
+    *r -= 1;
+    if *r == 0 {
+        d.used -= 1;
+    }
+}
+
+// page table entry of v in address space of pcb, page tables are identity mapped
+fn walk(pcb: &PCB, v: usize) -> Option<&'static mut PageTableEntry> {
+    let pde = &page_dir(pcb.pd)[v.pde_i()];
+    if !pde.exists() {
+        return None;
+    }
+    Some(cst!(pde.sub_table().off() + v.pte_i() * pe_size()))
+}
+
+// anonymous pages of all user processes as (pcb, virtual address)
+fn pages() -> impl Iterator<Item=(usize, usize)> {
+    all().iter().filter(|p| p.user()).flat_map(|p| {
+        let off = p.off();
+        p.vmas_ref().iter().filter(|x| x.backing == Backing::Anon).flat_map(move |x| {
+            let start = x.start;
+            (0..x.pages()).map(move |i| (off, start + i * PAGE_SIZE))
+        })
+    })
+}
+
+/// pick a page not accessed recently by clock algorithm, shared frames are skipped
+fn victim() -> Option<(&'static mut PCB, usize, &'static mut PageTableEntry)> {
+    let hand = unsafe { HAND };
+    let cur = current_pcb().off();
+    let after = pages().skip_while(move |x| *x != hand).skip(1);
+
+    // accessed bits are cleared in the first round
+    for (off, v) in after.chain(pages()).chain(pages()) {
+        let pcb: &'static mut PCB = cst!(off);
+        let e = match walk(pcb, v) {
+            Some(e) if e.exists() => e,
+            _ => continue,
+        };
+        if frame::shared(e.addr()) {
+            continue;
+        }
+        if e.has(PG_A) {
+            e.set(PG_A, false);
+            if off == cur {
+                crate::asm::invlpg(v);
+            }
+            continue;
+        }
+        unsafe { HAND = (off, v) };
+        return Some((pcb, v, e));
+    }
+    None
+}
+
+/// write a user page to swap and free its frame, return false if nothing can be evicted
+/// caller must hold u_lock
+pub fn swap_out() -> bool {
+    let d = match dev() {
+        Some(d) => d,
+        None => return false,
+    };
+    let slot = match d.alloc() {
+        Some(s) => s,
+        None => return false,
+    };
+    let (pcb, v, e) = match victim() {
+        Some(x) => x,
+        None => {
+            put(slot);
+            return false;
+        }
+    };
+
+    // the owner faults on the page from now on, and waits on u_lock until it is written
+    let p = e.addr();
+    e.write_swapped(slot);
+    if pcb.off() == current_pcb().off() {
+        crate::asm::invlpg(v);
+    }
+
+    let k = kmap(p);
+    let buf = unsafe { core::slice::from_raw_parts(k as *const u8, PAGE_SIZE) };
+    let r = d.part().write((slot * SECS_PER_PAGE) as u32, buf, SECS_PER_PAGE);
+    kunmap(k);
+
+    // keep the page in memory if it cannot be written
+    if r.is_err() {
+        e.write(p, vma::find(pcb, v).unwrap().pte_flags());
+        if pcb.off() == current_pcb().off() {
+            crate::asm::invlpg(v);
+        }
+        put(slot);
+        return false;
+    }
+
+    let up = user_pool();
+    up.remove(p);
+    up.avl_pages += 1;
+    true
+}
+
+/// read page v of current process back from swap
+pub fn swap_in(vma: &Vma, v: usize) -> Result<(), SE> {
+    let lk = u_lock();
+    let _gd = lk.map(|x| x.lock());
+
+    let e = pte(v).ok_or("page not swapped")?;
+    // a failed swap out maps the page back
+    if e.exists() {
+        return Ok(());
+    }
+    let slot = e.swap_slot().ok_or("page not swapped")?;
+    let d = dev().ok_or("no swap device")?;
+
+    let p = user_frame()?;
+    let k = kmap(p);
+    let buf = unsafe { core::slice::from_raw_parts_mut(k as *mut u8, PAGE_SIZE) };
+    let r = d.part().read((slot * SECS_PER_PAGE) as u32, buf, SECS_PER_PAGE);
+    kunmap(k);
+
+    // the entry stays swapped, only the faulting process fails
+    if let Err(err) = r {
+        let up = user_pool();
+        up.remove(p);
+        up.avl_pages += 1;
+        return Err(err);
+    }
+
+    // the page is private now, other references read their own copy
+    e.write(p, vma.pte_flags());
+    crate::asm::invlpg(v);
+    put(slot);
+    Ok(())
+}
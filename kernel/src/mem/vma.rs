@@ -0,0 +1,204 @@
+use rlib::link::{LinkedList, Node};
+use rlib::size_of;
+
+use crate::err::SE;
+use crate::mem::arena::{k_free, k_malloc};
+use crate::mem::PAGE_SIZE;
+use crate::mem::page::{PG_NX, PG_P, PG_RW, PG_US};
+use crate::println;
+use crate::thread::PCB;
+
+pub const VMA_PADDING: usize = 32;
+
+// permission bits of a virtual memory area
+pub const VM_READ: u32 = 1;
+pub const VM_WRITE: u32 = 1 << 1;
+pub const VM_EXEC: u32 = 1 << 2;
+pub const VM_SHARED: u32 = 1 << 3;
+// the area grows downward on page fault below it, for stack
+pub const VM_GROWS_DOWN: u32 = 1 << 4;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum VmaKind {
+    Code,
+    Data,
+    Heap,
+    Stack,
+    Mmap,
+    Shm,
+}
+
+impl VmaKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            VmaKind::Code => "[code]",
+            VmaKind::Data => "[data]",
+            VmaKind::Heap => "[heap]",
+            VmaKind::Stack => "[stack]",
+            VmaKind::Mmap => "[mmap]",
+            VmaKind::Shm => "[shm]",
+        }
+    }
+}
+
+// where the content of the pages comes from
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Backing {
+    // zero filled memory
+    Anon,
+    // shared memory segment id and the address it is attached at
+    Shm(usize, usize),
+}
+
+/// a continuous region [start, end) of user address space
+/// start and end are aligned to PAGE_SIZE
+#[repr(C)]
+pub struct Vma {
+    pointers: [usize; 2],
+    pub start: usize,
+    pub end: usize,
+    pub flags: u32,
+    pub kind: VmaKind,
+    pub backing: Backing,
+}
+
+impl Node for Vma {
+    fn pointers_mut(&mut self) -> &mut [usize] {
+        &mut self.pointers
+    }
+
+    fn pointers(&self) -> &[usize] {
+        &self.pointers
+    }
+}
+
+impl Vma {
+    #[inline]
+    pub fn contains(&self, v: usize) -> bool {
+        v >= self.start && v < self.end
+    }
+
+    #[inline]
+    pub fn overlaps(&self, start: usize, end: usize) -> bool {
+        start < self.end && end > self.start
+    }
+
+    #[inline]
+    pub fn pages(&self) -> usize {
+        (self.end - self.start) / PAGE_SIZE
+    }
+
+    pub fn readable(&self) -> bool {
+        self.flags & VM_READ != 0
+    }
+
+    pub fn writable(&self) -> bool {
+        self.flags & VM_WRITE != 0
+    }
+
+    pub fn executable(&self) -> bool {
+        self.flags & VM_EXEC != 0
+    }
+
+    // flags of page table entries in this area
+    pub fn pte_flags(&self) -> u16 {
+        let mut f = PG_P | PG_US;
+        if self.writable() {
+            f |= PG_RW;
+        }
+        if !self.executable() {
+            f |= PG_NX;
+        }
+        f
+    }
+
+    // no access is permitted, e.g. guard page
+    pub fn inaccessible(&self) -> bool {
+        self.flags & (VM_READ | VM_WRITE | VM_EXEC) == 0
+    }
+}
+
+pub type VmaList = LinkedList<Vma, VMA_PADDING>;
+
+fn alloc(start: usize, end: usize, flags: u32, kind: VmaKind, backing: Backing) -> &'static mut Vma {
+    let p = k_malloc(size_of!(Vma));
+    let vma: &'static mut Vma = cst!(p);
+    vma.pointers.fill(0);
+    vma.start = start;
+    vma.end = end;
+    vma.flags = flags;
+    vma.kind = kind;
+    vma.backing = backing;
+    vma
+}
+
+/// create a vma [start, end) in address space of pcb, the list is sorted by start address
+pub fn add(pcb: &mut PCB, start: usize, end: usize, flags: u32, kind: VmaKind) -> Result<&'static mut Vma, SE> {
+    assert!(start % PAGE_SIZE == 0 && end % PAGE_SIZE == 0, "vma 0x{:08X}-0x{:08X} not aligned", start, end);
+    if start >= end {
+        return Err("empty vma");
+    }
+
+    if find_overlap(pcb, start, end).is_some() {
+        return Err("vma overlap");
+    }
+
+    let vma = alloc(start, end, flags, kind, Backing::Anon);
+    let list = pcb.vmas();
+    match list.iter().find(|x| x.start >= end) {
+        Some(next) => list.insert_before(next, vma),
+        None => list.append(vma),
+    }
+    Ok(vma)
+}
+
+/// split vma at address at, the new area [start, at) is inserted before vma, vma becomes [at, end)
+/// return the new area
+pub fn split(pcb: &mut PCB, vma: &mut Vma, at: usize) -> Result<&'static mut Vma, SE> {
+    assert!(at > vma.start && at < vma.end && at % PAGE_SIZE == 0, "invalid split point 0x{:08X}", at);
+
+    let lo = alloc(vma.start, at, vma.flags, vma.kind, vma.backing);
+    vma.start = at;
+    pcb.vmas().insert_before(vma, lo);
+    Ok(lo)
+}
+
+/// duplicate areas of src into dst, for fork
+pub fn copy(src: &PCB, dst: &mut PCB) {
+    for x in src.vmas_ref().iter() {
+        let n = alloc(x.start, x.end, x.flags, x.kind, x.backing);
+        dst.vmas().append(n);
+    }
+}
+
+/// find the vma which contains virtual address v
+pub fn find(pcb: &PCB, v: usize) -> Option<&'static mut Vma> {
+    pcb.vmas_ref().iter().find(|x| x.contains(v))
+}
+
+pub fn find_overlap(pcb: &PCB, start: usize, end: usize) -> Option<&'static mut Vma> {
+    pcb.vmas_ref().iter().find(|x| x.overlaps(start, end))
+}
+
+/// unlink the vma from pcb and free it
+pub fn remove(pcb: &mut PCB, vma: &'static mut Vma) {
+    pcb.vmas().remove(vma);
+    k_free(vma as *const _ as usize);
+}
+
+/// print areas of the process like /proc/<pid>/maps
+pub fn dump(pcb: &PCB) {
+    println!("maps of {}:", pcb.name());
+    for x in pcb.vmas_ref().iter() {
+        println!(
+            "{:08x}-{:08x} {}{}{}{} {}",
+            x.start,
+            x.end,
+            if x.flags & VM_READ != 0 { 'r' } else { '-' },
+            if x.flags & VM_WRITE != 0 { 'w' } else { '-' },
+            if x.flags & VM_EXEC != 0 { 'x' } else { '-' },
+            if x.flags & VM_SHARED != 0 { 's' } else { 'p' },
+            x.kind.name()
+        );
+    }
+}
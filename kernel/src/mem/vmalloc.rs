@@ -0,0 +1,78 @@
+use rlib::div_up;
+
+use crate::err::SE;
+use crate::mem::{fill_zero, k_lock, kernel_pool, PAGE_SIZE, u_lock, user_pool, vmalloc_pool};
+use crate::mem::alloc::{PAlloc, VAlloc};
+use crate::mem::stat::{self, Owner};
+use crate::mem::page::{map_page, PDE_START, PG_NX, PG_P, PG_RW, pte, USER_P_START, VMALLOC_END, VMALLOC_START};
+
+/// allocate size bytes of virtually continuous kernel memory
+/// physical pages come from user pool first, then kernel pool
+/// every area is followed by an unmapped guard page
+pub fn vmalloc(size: usize) -> Result<usize, SE> {
+    if size == 0 {
+        return Err("invalid size");
+    }
+    let pages = div_up!(size, PAGE_SIZE);
+
+    let lk = u_lock();
+    let _gd = lk.map(|x| x.lock());
+    let klk = k_lock();
+    let _kgd = klk.map(|x| x.lock());
+
+    let v = vmalloc_pool();
+    let start = v.v_alloc(pages + 1)?;
+
+    for i in 0..pages {
+        let p = match user_pool().p_alloc().or_else(|_| kernel_pool().p_alloc()) {
+            Ok(p) => {
+                stat::set(p, Owner::KernelHeap);
+                p
+            }
+            Err(e) => {
+                release(start, i);
+                return Err(e);
+            }
+        };
+        if let Err(e) = map_page(PDE_START, start + i * PAGE_SIZE, p, PG_P | PG_RW | PG_NX, false, true) {
+            // the frame is not mapped yet, so release misses it
+            let phy = if p >= USER_P_START { user_pool() } else { kernel_pool() };
+            phy.remove(p);
+            phy.avl_pages += 1;
+            release(start, i);
+            return Err(e);
+        }
+    }
+
+    fill_zero(start, pages * PAGE_SIZE);
+    Ok(start)
+}
+
+/// free an area returned by vmalloc
+pub fn vfree(addr: usize) {
+    assert!(
+        addr >= VMALLOC_START && addr < VMALLOC_END && addr % PAGE_SIZE == 0,
+        "0x{:08X} is not allocated by vmalloc", addr
+    );
+
+    let lk = u_lock();
+    let _gd = lk.map(|x| x.lock());
+    let klk = k_lock();
+    let _kgd = klk.map(|x| x.lock());
+
+    // the area ends at the guard page
+    let mut pages = 0;
+    while pte(addr + pages * PAGE_SIZE).map(|e| e.exists()).unwrap_or(false) {
+        pages += 1;
+    }
+    assert!(pages > 0, "0x{:08X} is not allocated by vmalloc", addr);
+    release(addr, pages);
+}
+
+// free mapped pages of [start, start + pages) and the guard page after them
+fn release(start: usize, pages: usize) {
+    // VPool::free picks the physical pool by address
+    let v = vmalloc_pool();
+    v.free(start, pages);
+    v.remove(start + pages * PAGE_SIZE, 1);
+}
@@ -0,0 +1,302 @@
+use core::fmt;
+use core::fmt::Write;
+
+use crate::asm::{in_b, out_b, out_s};
+use crate::err::SE;
+use crate::input::{BACKSPACE, getc};
+use crate::int::{counts, disable_int, EXCEPTIONS};
+use crate::mem::page::mapped;
+use crate::mem::stat::{self, OWNERS};
+use crate::thread::{current_pcb, DEFAULT_PRIORITY, frames, new_thread, pid, Status};
+use crate::thread::data::{all, dead};
+
+const LINE_MAX: usize = 80;
+const MAX_FRAMES: usize = 16;
+const PEEK_MAX: usize = 64;
+const KBC_STATUS: u16 = 0x64;
+const KBC_RESET: u8 = 0xfe;
+
+// print to both console and com1
+macro_rules! out {
+    ($($arg:tt)*) => {
+        {
+            let lock = $crate::vga::vga_lock();
+            let _gd = lock.map(|x| x.lock());
+            Out {}.write_fmt(format_args!($($arg)*));
+        }
+    };
+}
+
+macro_rules! outln {
+    () => (out!("\n"));
+    ($($arg:tt)*) => {
+        {
+            out!($($arg)*);
+            out!("\n");
+        }
+    };
+}
+
+struct Out {}
+
+impl fmt::Write for Out {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        crate::vga::puts(s);
+        out_s(s);
+        Ok(())
+    }
+}
+
+const HELP: &[(&str, &str)] = &[
+    ("help", "show commands"),
+    ("ps", "list threads"),
+    ("mem", "memory pools and allocations"),
+    ("irq", "interrupt counters"),
+    ("disks", "ide disks and partitions"),
+    ("peek <addr> [words]", "read kernel memory"),
+    ("poke <addr> <word>", "write kernel memory"),
+    ("bt <pid>", "kernel stack backtrace of thread"),
+    ("reboot", "reset the machine"),
+];
+
+/// start the monitor thread, it reads commands from keyboard and com1
+pub fn init() {
+    crate::input::init();
+    new_thread(monitor, 0, "monitor", DEFAULT_PRIORITY);
+}
+
+extern "C" fn monitor(_: usize) {
+    let mut buf = [0u8; LINE_MAX];
+    loop {
+        out!("mos> ");
+        let n = read_line(&mut buf);
+        let line = core::str::from_utf8(&buf[..n]).unwrap_or("");
+        let mut args = line.split_whitespace();
+        let cmd = match args.next() {
+            Some(x) => x,
+            None => continue,
+        };
+        let r = match cmd {
+            "help" => help(),
+            "ps" => ps(),
+            "mem" => mem(),
+            "irq" => irq(),
+            "disks" => disks(),
+            "peek" => peek(args.next(), args.next()),
+            "poke" => poke(args.next(), args.next()),
+            "bt" => bt(args.next()),
+            "reboot" => reboot(),
+            _ => Err("unknown command, try help"),
+        };
+        if let Err(e) = r {
+            outln!("{}: {}", cmd, e);
+        }
+    }
+}
+
+// read a line with echo, return its length
+fn read_line(buf: &mut [u8]) -> usize {
+    let mut n = 0;
+    loop {
+        match getc() {
+            b'\n' => {
+                outln!();
+                return n;
+            }
+            BACKSPACE => {
+                if n > 0 {
+                    n -= 1;
+                    crate::vga::back();
+                    out_s("\x08 \x08");
+                }
+            }
+            c if (c == b' ' || c.is_ascii_graphic()) && n < buf.len() => {
+                buf[n] = c;
+                n += 1;
+                out!("{}", c as char);
+            }
+            _ => {}
+        }
+    }
+}
+
+// hex with 0x prefix or decimal
+fn parse(s: Option<&str>) -> Result<usize, SE> {
+    let s = s.ok_or("missing argument")?;
+    let r = match s.strip_prefix("0x") {
+        Some(h) => usize::from_str_radix(h, 16),
+        None => s.parse(),
+    };
+    r.map_err(|_| "invalid number")
+}
+
+fn help() -> Result<(), SE> {
+    for (cmd, desc) in HELP {
+        outln!("{:<22}{}", cmd, desc);
+    }
+    Ok(())
+}
+
+// fields of a thread printed by ps
+#[derive(Clone, Copy)]
+struct PsRow {
+    pid: usize,
+    parent: usize,
+    stat: &'static str,
+    priority: u8,
+    ticks: u8,
+    elapsed: u32,
+    user: bool,
+    name_len: usize,
+    name: [u8; 16],
+}
+
+fn ps() -> Result<(), SE> {
+    outln!("{:>5} {:>5} {:<8} {:>4} {:>5} {:>10} {}", "PID", "PPID", "STAT", "PRI", "TICKS", "ELAPSED", "NAME");
+    let empty = PsRow {
+        pid: 0, parent: 0, stat: "", priority: 0, ticks: 0, elapsed: 0, user: false, name_len: 0, name: [0; 16],
+    };
+    let mut rows = [empty; 64];
+    let mut n = 0;
+    // copy while interrupts are disabled, the reaper may free a pcb once they are enabled
+    let old = disable_int();
+    for x in all().iter().chain(dead().iter()) {
+        if n == rows.len() {
+            break;
+        }
+        let r = &mut rows[n];
+        r.pid = x.pid;
+        r.parent = x.parent;
+        r.stat = match x.status {
+            Status::Ready => "ready",
+            Status::Running => "running",
+            Status::Blocked => "blocked",
+            Status::Waiting => "waiting",
+            Status::Hanging => "hanging",
+            Status::Died => "zombie",
+        };
+        r.priority = x.priority();
+        r.ticks = x.ticks;
+        r.elapsed = x.elapsed_ticks();
+        r.user = x.user();
+        let name = x.name().as_bytes();
+        r.name_len = name.len().min(r.name.len());
+        r.name[..r.name_len].copy_from_slice(&name[..r.name_len]);
+        n += 1;
+    }
+    crate::int::set_int(old);
+
+    // print without interrupts disabled, since printing may block on the console lock
+    for r in rows[..n].iter() {
+        let name = core::str::from_utf8(&r.name[..r.name_len]).unwrap_or("?");
+        outln!(
+            "{:>5} {:>5} {:<8} {:>4} {:>5} {:>10} {}{}",
+            r.pid, r.parent, r.stat, r.priority, r.ticks, r.elapsed, name,
+            if r.user { "" } else { " [kernel]" }
+        );
+    }
+    Ok(())
+}
+
+fn mem() -> Result<(), SE> {
+    let m = stat::report(current_pcb());
+    let kb = |pages: u32| pages as usize * m.page_size as usize / 1024;
+    outln!("kernel pool {} KB, free {} KB", kb(m.kernel_pages), kb(m.kernel_free));
+    outln!("user pool   {} KB, free {} KB", kb(m.user_pages), kb(m.user_free));
+    outln!("swap        {} KB, used {} KB", kb(m.swap_pages), kb(m.swap_used));
+    for o in OWNERS.iter() {
+        outln!("  {:<12} {} pages", o.name(), m.owners[*o as usize]);
+    }
+    for a in m.arena.iter() {
+        outln!("  arena {:>4} bytes: {} blocks, {} free", a.blk_sz, a.blocks, a.frees);
+    }
+    Ok(())
+}
+
+fn irq() -> Result<(), SE> {
+    for (vec, n) in counts().iter().enumerate().filter(|x| *x.1 != 0) {
+        let name = match vec {
+            0x20 => "timer",
+            0x21 => "keyboard",
+            0x24 => "com1",
+            0x2e => "ide-0",
+            0x2f => "ide-1",
+            v if v < EXCEPTIONS.len() => EXCEPTIONS[v],
+            _ => "",
+        };
+        outln!("0x{:02x} {:>10} {}", vec, n, name);
+    }
+    Ok(())
+}
+
+fn disks() -> Result<(), SE> {
+    outln!("{} disks", crate::fs::disks());
+    for d in crate::fs::ide::disks() {
+        outln!("{} on {}", d.name(), d.ide().name());
+        for p in d.primary_parts.iter().flatten() {
+            outln!("  {} type 0x{:02x} start {} sectors {}", p.name(), p.fs_type(), p.start(), p.sectors());
+        }
+    }
+    Ok(())
+}
+
+fn peek(addr: Option<&str>, words: Option<&str>) -> Result<(), SE> {
+    let a = parse(addr)? & !3;
+    let n = if words.is_some() { parse(words)?.min(PEEK_MAX) } else { 4 };
+    for i in 0..n {
+        let v = a + i * 4;
+        if i % 4 == 0 {
+            if i != 0 {
+                outln!();
+            }
+            out!("{:08x}:", v);
+        }
+        if !mapped(v) {
+            outln!();
+            return Err("address not mapped");
+        }
+        out!(" {:08x}", unsafe { *(v as *const u32) });
+    }
+    outln!();
+    Ok(())
+}
+
+fn poke(addr: Option<&str>, word: Option<&str>) -> Result<(), SE> {
+    let a = parse(addr)? & !3;
+    let w = parse(word)? as u32;
+    if !mapped(a) {
+        return Err("address not mapped");
+    }
+    unsafe { *(a as *mut u32) = w };
+    Ok(())
+}
+
+fn bt(pid: Option<&str>) -> Result<(), SE> {
+    let x = pid::find(parse(pid)?).ok_or("no such thread")?;
+    if x.status == Status::Died {
+        return Err("thread exited");
+    }
+    let ebp = if x.off() == current_pcb().off() {
+        bp!() as usize
+    } else {
+        x.kernel_ctx().ebp as usize
+    };
+    outln!("{} {}:", x.pid, x.name());
+    for ret in frames(x, ebp).take(MAX_FRAMES) {
+        outln!("  0x{:08x}", ret);
+    }
+    Ok(())
+}
+
+fn reboot() -> Result<(), SE> {
+    outln!("rebooting");
+    disable_int();
+    // reset line of keyboard controller
+    while in_b(KBC_STATUS) & 2 != 0 {}
+    out_b(KBC_STATUS, KBC_RESET);
+
+    // triple fault if the controller does not reset
+    let null = [0u16; 3];
+    unsafe { asm!("lidt [{}]", "int3", in(reg) &null) };
+    Err("reset failed")
+}
@@ -1,12 +1,37 @@
 use core::ops::Add;
 
+use rlib::size_of;
+use rlib::args::ARG_MAX;
+use rlib::sys::{MAP_FAILED, MemInfo, SCHED_FIFO, SCHED_NORMAL, SCHED_RR};
+
+use crate::err::SE;
+use crate::mem::{mmap, shm, stat};
+use crate::mem::arena::{k_free, k_malloc};
+use crate::mem::page::OS_MEM_OFF;
 use crate::println;
+use crate::thread::{current_pcb, PCB, pi, pid, Status};
+use crate::thread::exec::Args;
+use crate::thread::exit::{thread_exit, wait};
+use crate::thread::pid::INIT_PID;
+use crate::thread::rt::{Class, set_scheduler};
 use crate::thread::reg::IntCtx;
 use crate::vga::put_char;
 
+// 0 for success, -1 for failure
+fn ret(r: Result<(), SE>) -> u32 {
+    if r.is_ok() { 0 } else { u32::MAX }
+}
+
 pub fn sys_handle(ctx: &'static mut IntCtx) {
     use rlib::sys::NR;
+    let cur = current_pcb();
     match ctx.eax {
+        NR::GET_PID => {
+            ctx.eax = cur.pid as u32;
+        }
+        NR::GET_PPID => {
+            ctx.eax = cur.parent as u32;
+        }
         NR::WRITE => {
             let p: *const u8 = ctx.ebx as _;
             for i in 0..ctx.ecx {
@@ -21,10 +46,127 @@ pub fn sys_handle(ctx: &'static mut IntCtx) {
             crate::mem::arena::free(ctx.ebx as usize);
             ctx.eax = 0;
         }
+        NR::BRK => {
+            ctx.eax = mmap::brk(cur, ctx.ebx as usize) as u32;
+        }
+        NR::MMAP => {
+            let r = mmap::mmap(cur, ctx.ebx as usize, ctx.ecx as usize, ctx.edx);
+            ctx.eax = r.unwrap_or(MAP_FAILED) as u32;
+        }
+        NR::MUNMAP => {
+            ctx.eax = ret(mmap::munmap(cur, ctx.ebx as usize, ctx.ecx as usize));
+        }
+        NR::MPROTECT => {
+            ctx.eax = ret(mmap::mprotect(cur, ctx.ebx as usize, ctx.ecx as usize, ctx.edx));
+        }
+        NR::SHM_OPEN => {
+            ctx.eax = shm::open(ctx.ebx, ctx.ecx as usize).map(|x| x as u32).unwrap_or(u32::MAX);
+        }
+        NR::SHM_ATTACH => {
+            let r = shm::attach(cur, ctx.ebx as usize, ctx.ecx as usize);
+            ctx.eax = r.unwrap_or(MAP_FAILED) as u32;
+        }
+        NR::SHM_DETACH => {
+            ctx.eax = ret(shm::detach(cur, ctx.ebx as usize));
+        }
+        NR::SHM_UNLINK => {
+            ctx.eax = ret(shm::unlink(ctx.ebx));
+        }
+        NR::MEMINFO => {
+            let p = ctx.ebx as usize;
+            ctx.eax = match user_ptr::<MemInfo>(p) {
+                Ok(info) => {
+                    *info = stat::report(cur);
+                    0
+                }
+                Err(_) => u32::MAX,
+            };
+        }
+        NR::SCHED_SET => {
+            ctx.eax = ret(sched_set(ctx.ebx as usize, ctx.ecx, ctx.edx));
+        }
+        NR::EXIT => {
+            thread_exit(ctx.ebx as i32);
+        }
+        NR::WAITPID => {
+            ctx.eax = waitpid(ctx.ebx as usize, ctx.ecx as usize).map(|x| x as u32).unwrap_or(u32::MAX);
+        }
+        NR::EXEC => {
+            // a successful exec returns to the new program with a fresh context
+            if exec(ctx).is_err() {
+                ctx.eax = u32::MAX;
+            }
+        }
+        NR::FORK => {
+            ctx.eax = crate::thread::user::fork(ctx).map(|x| x as u32).unwrap_or(u32::MAX);
+        }
         _ => {}
     }
 }
 
+fn user_ptr<T>(p: usize) -> Result<&'static mut T, SE> {
+    if p == 0 || p.checked_add(size_of!(T)).map(|x| x > OS_MEM_OFF).unwrap_or(true) {
+        return Err("invalid pointer");
+    }
+    Ok(cst!(p))
+}
+
+fn waitpid(pid: usize, code: usize) -> Result<usize, SE> {
+    let code: &mut i32 = user_ptr(code)?;
+    let (pid, c) = wait(pid)?;
+    *code = c;
+    Ok(pid)
+}
+
+fn exec(ctx: &mut IntCtx) -> Result<(), SE> {
+    let (p, len) = (ctx.ebx as usize, ctx.ecx as usize);
+    if len > ARG_MAX || p == 0 || p.checked_add(len).map(|x| x > OS_MEM_OFF).unwrap_or(true) {
+        return Err("invalid arguments");
+    }
+    // the arguments are in the address space to be freed
+    let buf = k_malloc(ARG_MAX);
+    let b = unsafe { core::slice::from_raw_parts_mut(buf as *mut u8, len) };
+    b.copy_from_slice(unsafe { core::slice::from_raw_parts(p as *const u8, len) });
+    let r = Args::new(b, (ctx.edx & 0xffff) as usize, (ctx.edx >> 16) as usize)
+        .and_then(|x| crate::thread::exec::exec(ctx, &x));
+    k_free(buf);
+    r
+}
+
+fn sched_set(pid: usize, policy: u32, priority: u32) -> Result<(), SE> {
+    let class = match policy {
+        SCHED_NORMAL => Class::Normal,
+        SCHED_FIFO => Class::Fifo,
+        SCHED_RR => Class::Rr,
+        _ => return Err("invalid policy"),
+    };
+    if priority > u8::MAX as u32 {
+        return Err("invalid priority");
+    }
+    let cur = current_pcb();
+    let pcb: &mut PCB = if pid == 0 || pid == cur.pid {
+        current_pcb()
+    } else {
+        pid::find(pid).filter(|x| x.status != Status::Died).ok_or("no such thread")?
+    };
+
+    // a process only changes itself and its children
+    if pcb.pid != cur.pid && (pcb.parent != cur.pid || !pcb.user()) {
+        return Err("permission denied");
+    }
+    // only init raises priority, others keep the class or lower it
+    let base = pi::base(pcb);
+    let raise = match (class, base.class) {
+        (Class::Normal, Class::Normal) => priority as u8 > base.priority,
+        (Class::Normal, _) => false,
+        (_, Class::Normal) => true,
+        _ => priority as u8 > base.rt_priority,
+    };
+    if raise && cur.pid != INIT_PID {
+        return Err("permission denied");
+    }
+    set_scheduler(pcb, class, priority as u8)
+}
 
 pub fn init() {
     crate::int::register(crate::int::SYS_VEC as u16, sys_handle);
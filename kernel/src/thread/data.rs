@@ -6,6 +6,8 @@ use crate::thread::PCB_PADDING;
 
 alloc_static!(ALL, all, LinkedList<PCB, PCB_PADDING>);
 alloc_static!(READY, ready, LinkedList<PCB, PCB_PADDING>);
+// exited threads waiting to be reaped, linked by the same pointers as all
+alloc_static!(DEAD, dead, LinkedList<PCB, PCB_PADDING>);
 
 pub fn init() {
     // general tag
@@ -14,4 +16,6 @@ pub fn init() {
 
     let r = ready();
     r.init(2, 3);
+
+    dead().init(0, 1);
 }
\ No newline at end of file
@@ -0,0 +1,292 @@
+use rlib::args::{ARG_MAX, ARGS_MAX, pack, unpack};
+use rlib::div_up;
+use rlib::elf::Elf;
+
+use crate::{Pool, v2p};
+use crate::asm::SELECTOR_K_DATA;
+use crate::err::SE;
+use crate::int::{disable_int, set_int};
+use crate::mem::{fill_zero, PAGE_SIZE, pg_alloc, pg_free, u_lock};
+use crate::mem::alloc::{reserve, user_frame};
+use crate::mem::arena::{k_free, k_malloc};
+use crate::mem::fault::DEFAULT_STACK_LIMIT;
+use crate::mem::page::{cr3, kmap, kunmap, map_user_page, OS_MEM_OFF, USER_V_START};
+use crate::mem::stat::{self, Owner};
+use crate::mem::vma::{self, VM_EXEC, VM_READ, VM_WRITE, VmaKind};
+use crate::thread::{current_pcb, DEFAULT_PRIORITY, PCB, PCB_PAGES, pid, prog};
+use crate::thread::data::all;
+use crate::thread::exit::thread_exit;
+use crate::thread::reg::{IntCtx, KernelCtx};
+use crate::thread::sched::{Enqueue, sched};
+use crate::thread::user::{fork_ret, free_space, init_space, init_stack, user_ctx};
+
+/// exit code of a process killed by exec after its old image is freed
+pub const EXEC_FAIL_EXIT: i32 = 127;
+
+/// path, arguments and environment packed by rlib::args::pack
+pub struct Args<'a> {
+    buf: &'a [u8],
+    argc: usize,
+    envc: usize,
+}
+
+impl<'a> Args<'a> {
+    pub fn new(buf: &'a [u8], argc: usize, envc: usize) -> Result<Self, SE> {
+        if buf.len() > ARG_MAX || argc + envc > ARGS_MAX || unpack(buf).count() != 1 + argc + envc {
+            return Err("invalid arguments");
+        }
+        Ok(Self { buf, argc, envc })
+    }
+
+    pub fn path(&self) -> &'a [u8] {
+        unpack(self.buf).next().unwrap()
+    }
+
+    pub fn argv(&self) -> impl Iterator<Item=&'a [u8]> {
+        unpack(self.buf).skip(1).take(self.argc)
+    }
+
+    pub fn envp(&self) -> impl Iterator<Item=&'a [u8]> {
+        unpack(self.buf).skip(1 + self.argc)
+    }
+
+    // last component of path
+    fn name(&self) -> &'a str {
+        let p = self.path();
+        let n = p.rsplit(|x| *x == b'/').next().unwrap_or(p);
+        core::str::from_utf8(n).unwrap_or("?")
+    }
+}
+
+#[inline]
+fn page_down(x: usize) -> usize {
+    x & !(PAGE_SIZE - 1)
+}
+
+#[inline]
+fn page_up(x: usize) -> usize {
+    div_up!(x, PAGE_SIZE) * PAGE_SIZE
+}
+
+// pages of a segment
+fn range(start: usize, end: usize) -> (usize, usize) {
+    (page_down(start), page_up(end))
+}
+
+/// segments must be in user space below the stack and not share pages
+fn check(elf: &Elf) -> Result<(), SE> {
+    let segs = || elf.segments().filter(|x| x.mem_size != 0);
+    if segs().next().is_none() {
+        return Err("no loadable segment");
+    }
+    for (i, a) in segs().enumerate() {
+        let (start, end) = range(a.v_addr as usize, a.end());
+        if start < USER_V_START || end > OS_MEM_OFF - DEFAULT_STACK_LIMIT {
+            return Err("segment out of user space");
+        }
+        if segs().skip(i + 1).any(|b| {
+            let (s, e) = range(b.v_addr as usize, b.end());
+            s < end && e > start
+        }) {
+            return Err("segments overlap");
+        }
+    }
+    let e = elf.entry();
+    if !segs().any(|x| x.executable() && e >= x.v_addr as usize && e < x.end()) {
+        return Err("entry not in code");
+    }
+    Ok(())
+}
+
+/// map segments of elf into address space of pcb, the program break follows the last segment
+/// caller must hold u_lock
+fn load(pcb: &mut PCB, elf: &Elf) -> Result<(), SE> {
+    let mut brk = 0;
+    for ph in elf.segments().filter(|x| x.mem_size != 0) {
+        let (start, end) = range(ph.v_addr as usize, ph.end());
+        let mut flags = 0;
+        if ph.readable() {
+            flags |= VM_READ;
+        }
+        if ph.writable() {
+            flags |= VM_WRITE;
+        }
+        if ph.executable() {
+            flags |= VM_EXEC;
+        }
+        let kind = if ph.executable() { VmaKind::Code } else { VmaKind::Data };
+        let x = vma::add(pcb, start, end, flags, kind)?;
+        reserve(pcb, start, x.pages())?;
+
+        // file content is copied into zeroed frames, the rest is bss
+        let data = elf.file_data(&ph);
+        let base = ph.v_addr as usize;
+        for v in (start..end).step_by(PAGE_SIZE) {
+            let p = user_frame()?;
+            let k = kmap(p);
+            fill_zero(k, PAGE_SIZE);
+            let lo = v.max(base);
+            let hi = (v + PAGE_SIZE).min(base + data.len());
+            if lo < hi {
+                let dst = unsafe { core::slice::from_raw_parts_mut((k + lo - v) as *mut u8, hi - lo) };
+                dst.copy_from_slice(&data[lo - base..hi - base]);
+            }
+            kunmap(k);
+            map_user_page(pcb.pd, v, p, x.pte_flags())?;
+        }
+        brk = brk.max(end);
+    }
+
+    pcb.brk_start = brk;
+    pcb.brk = brk;
+    Ok(())
+}
+
+/// map the top stack page with argc, argv and envp like i386 System V ABI, return initial esp
+/// caller must hold u_lock
+fn setup_stack(pcb: &mut PCB, args: &Args) -> Result<usize, SE> {
+    let x = init_stack(pcb)?;
+    let base = OS_MEM_OFF - PAGE_SIZE;
+    let p = user_frame()?;
+    let k = kmap(p);
+    fill_zero(k, PAGE_SIZE);
+    let page = unsafe { core::slice::from_raw_parts_mut(k as *mut u8, PAGE_SIZE) };
+
+    // strings at the top of stack, terminated by zero of the page
+    let mut sp = PAGE_SIZE;
+    let mut push = |s: &[u8]| {
+        sp -= s.len() + 1;
+        page[sp..sp + s.len()].copy_from_slice(s);
+        (base + sp) as u32
+    };
+
+    // argc, argv[..], 0, envp[..], 0
+    let mut words = [0u32; ARGS_MAX + 3];
+    words[0] = args.argc as u32;
+    let mut n = 1;
+    for s in args.argv() {
+        words[n] = push(s);
+        n += 1;
+    }
+    n += 1;
+    for s in args.envp() {
+        words[n] = push(s);
+        n += 1;
+    }
+    n += 1;
+
+    sp = (sp - n * 4) & !15;
+    for (i, w) in words[..n].iter().enumerate() {
+        page[sp + i * 4..sp + i * 4 + 4].copy_from_slice(&w.to_le_bytes());
+    }
+    kunmap(k);
+    map_user_page(pcb.pd, base, p, x.pte_flags())?;
+    Ok(base + sp)
+}
+
+// build image and stack of program in fresh address space of pcb, return (eip, esp)
+fn build(pcb: &mut PCB, elf: &Elf, args: &Args) -> Result<(usize, usize), SE> {
+    let lk = u_lock();
+    let _gd = lk.map(|x| x.lock());
+    load(pcb, elf)?;
+    Ok((elf.entry(), setup_stack(pcb, args)?))
+}
+
+/// replace program of current process, ctx is the syscall context
+/// the old address space is freed once the new image is checked, so a later failure kills the process
+pub fn exec(ctx: &mut IntCtx, args: &Args) -> Result<(), SE> {
+    let cur = current_pcb();
+    if !cur.user() {
+        return Err("not a process");
+    }
+    let image = prog::find(args.path()).ok_or("program not found")?;
+    let elf = Elf::parse(image)?;
+    check(&elf)?;
+
+    free_space(cur);
+    if init_space(cur).is_err() {
+        thread_exit(EXEC_FAIL_EXIT);
+    }
+    let old = disable_int();
+    unsafe { asm!("mov cr3, {}", in(reg) cr3(cur.pd)) };
+    set_int(old);
+
+    let (eip, esp) = match build(cur, &elf, args) {
+        Ok(x) => x,
+        Err(_) => thread_exit(EXEC_FAIL_EXIT),
+    };
+    cur.set_name(args.name());
+    user_ctx(ctx, eip, esp);
+    Ok(())
+}
+
+/// create a process running program path, return its pid
+pub fn spawn(path: &str, argv: &[&str], envp: &[&str]) -> Result<usize, SE> {
+    with_args(path, argv, envp, |x| spawn_args(x, false))
+}
+
+/// create the init process, it has no parent and gets pid::INIT_PID
+pub fn spawn_init(path: &str, argv: &[&str], envp: &[&str]) -> Result<usize, SE> {
+    with_args(path, argv, envp, |x| spawn_args(x, true))
+}
+
+// pack arguments into a kernel buffer for f
+fn with_args<F: FnOnce(&Args) -> Result<usize, SE>>(path: &str, argv: &[&str], envp: &[&str], f: F) -> Result<usize, SE> {
+    let buf = k_malloc(ARG_MAX);
+    let b = unsafe { core::slice::from_raw_parts_mut(buf as *mut u8, ARG_MAX) };
+    let r = match pack(b, path, argv, envp) {
+        Some(n) => Args::new(&b[..n], argv.len(), envp.len()).and_then(|x| f(&x)),
+        None => Err("argument list too long"),
+    };
+    k_free(buf);
+    r
+}
+
+fn spawn_args(args: &Args, init: bool) -> Result<usize, SE> {
+    let image = prog::find(args.path()).ok_or("program not found")?;
+    let elf = Elf::parse(image)?;
+    check(&elf)?;
+
+    let pcb_off = pg_alloc(Pool::KERNEL, 1, true)?;
+    stat::set(v2p(pcb_off), Owner::Pcb);
+    let pcb = PCB::new(args.name(), DEFAULT_PRIORITY, pcb_off);
+    if init {
+        if let Err(e) = pid::alloc_init(pcb) {
+            pg_free(pcb_off, PCB_PAGES);
+            return Err(e);
+        }
+    } else {
+        pcb.parent = current_pcb().pid;
+        pid::alloc(pcb);
+    }
+    if let Err(e) = init_space(pcb) {
+        pid::free(pcb);
+        pg_free(pcb_off, PCB_PAGES);
+        return Err(e);
+    }
+
+    let (eip, esp) = match build(pcb, &elf, args) {
+        Ok(x) => x,
+        Err(e) => {
+            free_space(pcb);
+            pid::free(pcb);
+            pg_free(pcb_off, PCB_PAGES);
+            return Err(e);
+        }
+    };
+
+    // the first switch returns to user space like a child of fork
+    pcb.stack -= core::mem::size_of::<IntCtx>();
+    user_ctx(pcb.int_ctx(), eip, esp);
+    pcb.stack -= core::mem::size_of::<KernelCtx>();
+    let k = pcb.kernel_ctx();
+    k.eip = fork_ret as usize as u32;
+    k.ds = SELECTOR_K_DATA as u32;
+    k.es = SELECTOR_K_DATA as u32;
+
+    let old = disable_int();
+    sched().enqueue(pcb, Enqueue::New);
+    all().append(pcb);
+    set_int(old);
+    Ok(pcb.pid)
+}
@@ -0,0 +1,130 @@
+use rlib::alloc_static;
+use rlib::link::LinkedList;
+
+use crate::err::SE;
+use crate::int::{disable_int, set_int};
+use crate::mem::pg_free;
+use crate::thread::{current_pcb, DEFAULT_PRIORITY, idle_thread, new_thread, PCB, PCB_PADDING, PCB_PAGES, pid, schedule, Status};
+use crate::thread::data::{all, dead};
+use crate::thread::pid::INIT_PID;
+use crate::thread::sync::{block, Semaphore, unblock};
+use crate::thread::user::free_space;
+
+/// exit code of a process killed by segmentation fault, 128 + SIGSEGV like shell
+pub const SEGV_EXIT: i32 = 139;
+
+// counts exits and collected exit codes, reaper runs once for each
+alloc_static!(REAP_SEM, reap_sem, Semaphore);
+// threads blocked in join or wait, linked by pointers[2, 3] of pcb
+alloc_static!(WAITERS, waiters, LinkedList<PCB, PCB_PADDING>);
+
+// called by thread::init
+pub fn init() {
+    let s = reap_sem();
+    s.value = 0;
+    s.waiters.init(2, 3);
+    waiters().init(2, 3);
+    new_thread(reaper, 0, "reaper", DEFAULT_PRIORITY);
+}
+
+/// terminate current thread, the thread must not hold any lock
+/// it stays in dead list until its parent collects the exit code by join or wait
+pub fn thread_exit(code: i32) -> ! {
+    let cur = current_pcb();
+    assert_ne!(cur.off(), idle_thread().off(), "idle thread exit");
+    if cur.pid == INIT_PID {
+        panic!("init exited with code {}, nothing is left to run user programs", code);
+    }
+    disable_int();
+
+    cur.exit_code = code;
+    cur.status = Status::Died;
+    all().remove(cur);
+    dead().append(cur);
+
+    // orphans are adopted by init, or freed by reaper without being waited before init starts
+    let adopter = if pid::find(INIT_PID).is_some() { INIT_PID } else { 0 };
+    for x in all().iter().chain(dead().iter()).filter(|x| x.parent == cur.pid) {
+        x.parent = adopter;
+    }
+
+    // let waiters search dead list again
+    while let Some(x) = waiters().pop_head() {
+        unblock(x);
+    }
+
+    reap_sem().v();
+    schedule("exit");
+    unreachable!("dead thread {} scheduled", cur.name());
+}
+
+/// wait for thread pid to exit and return its exit code
+/// only threads created by new_joinable_thread can be joined, once, and only until their creator exits
+/// detached threads are freed by reaper on exit, joining one fails once it exits
+pub fn join(pid: usize) -> Result<i32, SE> {
+    if pid == current_pcb().pid {
+        return Err("join self");
+    }
+    wait_for(|x| x.pid == pid, false).map(|x| x.1)
+}
+
+/// wait for a child of current thread to exit, any child if pid is 0
+/// return pid and exit code of the child
+/// init waiting for any child blocks even without children, since orphans may be adopted later
+pub fn wait(pid: usize) -> Result<(usize, i32), SE> {
+    let cur = current_pcb().pid;
+    wait_for(|x| x.parent == cur && (pid == 0 || x.pid == pid), cur == INIT_PID && pid == 0)
+}
+
+// block until a dead thread matches f, release it to reaper
+// without forever, fail if no live thread matches f
+fn wait_for<F: Fn(&PCB) -> bool>(f: F, forever: bool) -> Result<(usize, i32), SE> {
+    let old = disable_int();
+    let cur = current_pcb();
+    let r = loop {
+        if let Some(x) = dead().iter().find(|x| x.parent != 0 && f(x)) {
+            x.parent = 0;
+            reap_sem().v();
+            break Ok((x.pid, x.exit_code));
+        }
+        if !forever && !all().iter().any(|x| f(x)) {
+            break Err("no such thread");
+        }
+        waiters().append(cur);
+        block(Status::Waiting);
+    };
+    set_int(old);
+    r
+}
+
+// next dead thread which has something to free
+// a released thread is taken off dead list, so nobody sees it any more
+fn next() -> Option<(&'static mut PCB, bool)> {
+    let old = disable_int();
+    let r = dead().iter().find(|x| x.pd != 0 || x.parent == 0);
+    let r = r.map(|x| {
+        let release = x.parent == 0;
+        if release {
+            dead().remove(x);
+        }
+        (x, release)
+    });
+    set_int(old);
+    r
+}
+
+// address space is freed on exit, pcb is freed after exit code is collected
+extern "C" fn reaper(_: usize) {
+    loop {
+        reap_sem().p();
+        while let Some((x, release)) = next() {
+            if x.pd != 0 {
+                free_space(x);
+            }
+            if release {
+                pid::free(x);
+                pg_free(x.off(), PCB_PAGES);
+            }
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use core::panic::Location;
+
+use crate::c_println;
+use crate::int::{disable_int, set_int};
+use crate::thread::{current_pcb, frames};
+
+// locks told apart, locks of the same name or taken at the same site share a class
+const MAX_CLASSES: usize = 32;
+/// locks a thread can hold at once
+pub const MAX_DEPTH: usize = 8;
+// return addresses saved for an acquisition
+const STACK_DEPTH: usize = 6;
+
+type Stack = [u32; STACK_DEPTH];
+
+/// classes held by a thread in acquisition order, and addresses of the locks
+#[derive(Clone, Copy)]
+pub struct Held {
+    classes: [u8; MAX_DEPTH],
+    addrs: [usize; MAX_DEPTH],
+    n: u8,
+}
+
+impl Held {
+    pub const fn new() -> Self {
+        Self { classes: [0; MAX_DEPTH], addrs: [0; MAX_DEPTH], n: 0 }
+    }
+}
+
+/// class of a lock, its name, or the source line taking it if unnamed
+#[derive(Clone, Copy, PartialEq)]
+pub struct Key {
+    name: &'static str,
+    line: u32,
+}
+
+impl Key {
+    #[track_caller]
+    pub fn of(name: Option<&'static str>) -> Self {
+        match name {
+            Some(name) => Self { name, line: 0 },
+            None => {
+                let l = Location::caller();
+                Self { name: l.file(), line: l.line() }
+            }
+        }
+    }
+}
+
+// after the first report, checking stops like linux
+static mut DISABLED: bool = false;
+static mut CLASSES: [Key; MAX_CLASSES] = [Key { name: "", line: 0 }; MAX_CLASSES];
+static mut N_CLASSES: usize = 0;
+// bit j of AFTER[i] is set if class j was taken while holding class i
+static mut AFTER: [u32; MAX_CLASSES] = [0; MAX_CLASSES];
+// stack of the first acquisition of j while holding i, at i * MAX_CLASSES + j
+static mut EDGE_STACKS: [Stack; MAX_CLASSES * MAX_CLASSES] = [[0; STACK_DEPTH]; MAX_CLASSES * MAX_CLASSES];
+
+fn class_of(key: Key) -> Option<usize> {
+    let cs = unsafe { &mut CLASSES };
+    let n = unsafe { &mut N_CLASSES };
+    if let Some(i) = cs[..*n].iter().position(|x| *x == key) {
+        return Some(i);
+    }
+    if *n == MAX_CLASSES {
+        c_println!("lockdep: too many lock classes, turned off");
+        unsafe { DISABLED = true };
+        return None;
+    }
+    cs[*n] = key;
+    *n += 1;
+    Some(*n - 1)
+}
+
+fn print_class(i: usize) -> (&'static str, u32) {
+    let c = unsafe { CLASSES[i] };
+    (c.name, c.line)
+}
+
+fn stack() -> Stack {
+    let mut s = [0; STACK_DEPTH];
+    let ebp = bp!() as usize;
+    for (i, x) in frames(current_pcb(), ebp).take(STACK_DEPTH).enumerate() {
+        s[i] = x;
+    }
+    s
+}
+
+fn print_stack(s: &Stack) {
+    for x in s.iter().take_while(|x| **x != 0) {
+        c_println!("    0x{:08x}", x);
+    }
+}
+
+// classes on a path from `from` to `to`, by depth first search
+fn path(from: usize, to: usize, visited: &mut u32, out: &mut [u8; MAX_CLASSES], len: usize) -> Option<usize> {
+    out[len] = from as u8;
+    if from == to {
+        return Some(len + 1);
+    }
+    *visited |= 1 << from;
+    let after = unsafe { AFTER[from] };
+    for next in 0..MAX_CLASSES {
+        if after & (1 << next) != 0 && *visited & (1 << next) == 0 {
+            if let Some(n) = path(next, to, visited, out, len + 1) {
+                return Some(n);
+            }
+        }
+    }
+    None
+}
+
+fn report(held: usize, taking: usize, cycle: &[u8]) {
+    let (hn, hl) = print_class(held);
+    let (tn, tl) = print_class(taking);
+    c_println!("lockdep: possible deadlock in thread {}", current_pcb().name());
+    c_println!("  acquiring {}:{} while holding {}:{} at:", tn, tl, hn, hl);
+    print_stack(&stack());
+    c_println!("  reverse order was taken before:");
+    for w in cycle.windows(2) {
+        let (i, j) = (w[0] as usize, w[1] as usize);
+        let (iname, il) = print_class(i);
+        let (jname, jl) = print_class(j);
+        c_println!("  {}:{} while holding {}:{} at:", jname, jl, iname, il);
+        print_stack(unsafe { &EDGE_STACKS[i * MAX_CLASSES + j] });
+    }
+}
+
+/// current thread is going to block for a lock of key, check order against held locks
+/// a trylock never blocks, so it is not checked
+pub fn acquire(key: Key) {
+    let old = disable_int();
+    if unsafe { DISABLED } {
+        set_int(old);
+        return;
+    }
+    let c = match class_of(key) {
+        Some(c) => c,
+        None => {
+            set_int(old);
+            return;
+        }
+    };
+    let held = current_pcb().lockdep;
+    for h in held.classes[..held.n as usize].iter().map(|x| *x as usize).filter(|x| *x != c) {
+        if unsafe { AFTER[h] } & (1 << c) != 0 {
+            continue;
+        }
+        // new order h -> c, a path c -> h makes a cycle
+        let mut cycle = [0u8; MAX_CLASSES];
+        if let Some(n) = path(c, h, &mut 0, &mut cycle, 0) {
+            report(h, c, &cycle[..n]);
+            unsafe { DISABLED = true };
+            break;
+        }
+        unsafe {
+            AFTER[h] |= 1 << c;
+            EDGE_STACKS[h * MAX_CLASSES + c] = stack();
+        }
+    }
+    set_int(old);
+}
+
+/// lock at addr of key is taken by current thread
+pub fn acquired(addr: usize, key: Key) {
+    let old = disable_int();
+    if unsafe { DISABLED } {
+        set_int(old);
+        return;
+    }
+    if let Some(c) = class_of(key) {
+        let h = &mut current_pcb().lockdep;
+        if (h.n as usize) < MAX_DEPTH {
+            h.classes[h.n as usize] = c as u8;
+            h.addrs[h.n as usize] = addr;
+            h.n += 1;
+        }
+    }
+    set_int(old);
+}
+
+/// lock at addr is released by current thread, not necessarily the last taken
+pub fn release(addr: usize) {
+    let old = disable_int();
+    let h = &mut current_pcb().lockdep;
+    let len = h.n as usize;
+    if let Some(i) = h.addrs[..len].iter().rposition(|x| *x == addr) {
+        h.classes.copy_within(i + 1..len, i);
+        h.addrs.copy_within(i + 1..len, i);
+        h.n -= 1;
+    }
+    set_int(old);
+}
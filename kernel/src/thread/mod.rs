@@ -4,16 +4,22 @@ use rlib::link::{LinkedList, Node};
 use crate::asm::{switch, REG_CTX_LEN, SELECTOR_K_DATA};
 use crate::err::SE;
 use crate::mem::arena::{BlkDesc, DESC_CNT};
-use crate::mem::page::PDE_START;
+use crate::mem::vma::VmaList;
+use crate::mem::page::{cr3, page_dir, PDE_START};
+use crate::mem::stat::{self, Owner};
 use crate::mem::PagePool;
 use crate::mem::PageTable;
-use crate::mem::{fill_zero, pg_alloc, VPool, PAGE_SIZE, PT_LEN};
-use crate::thread::data::{all, ready};
+use crate::mem::{fill_zero, pg_alloc, VPool, PAGE_SIZE};
+use crate::thread::data::all;
+use crate::thread::pi::{MAX_HELD, Params};
+use crate::thread::rt::Class;
+use crate::thread::sched::{Enqueue, sched};
 use crate::thread::reg::IntCtx;
 use crate::thread::sync::{block, unblock};
 use crate::thread::tss::esp0;
 use crate::thread::Status::{Ready, Running};
-use crate::{c_println, print, println, Pool};
+use crate::timer::Timer;
+use crate::{c_println, print, println, Pool, v2p};
 
 use self::reg::KernelCtx;
 
@@ -29,8 +35,18 @@ macro_rules! debug {
 }
 
 pub mod data;
+pub mod exec;
+pub mod exit;
+#[cfg(feature = "lockdep")]
+pub mod lockdep;
+pub mod pi;
+pub mod pid;
+pub mod prog;
 pub mod reg;
+pub mod rt;
+pub mod sched;
 pub mod sync;
+pub mod timeout;
 pub mod tss;
 pub mod user;
 
@@ -83,6 +99,7 @@ pub extern "C" fn idle(args: usize) {
 pub extern "C" fn entry(fun: Routine, args: usize) {
     crate::asm::sti();
     fun(args);
+    exit::thread_exit(0);
 }
 
 #[repr(u8)]
@@ -97,15 +114,40 @@ pub enum Status {
 }
 
 // ready -> running
+// pointers[0, 1] link all or dead list, [2, 3] ready queue or waiters, [4, 5] pid table
 #[repr(C)]
 pub struct PCB {
     stack: usize,
-    pointers: [usize; 4],
+    pointers: [usize; 6],
     pub status: Status,
     priority: u8,
+    // remaining ticks of time slice
     pub ticks: u8,
+    // queue level of mlfq scheduler
+    pub level: u8,
+    pub class: Class,
+    // static priority of real time thread, higher runs first
+    pub rt_priority: u8,
     elapsed_ticks: u32,
     name_len: u8,
+    // thread id, unique among live and dead threads
+    pub pid: usize,
+    // pid of creator, it collects exit code by join or wait, 0 if no one
+    pub parent: usize,
+    pub exit_code: i32,
+    // wakes the thread blocked with a deadline
+    pub timer: Timer,
+    // waiters list blocked on with a timeout, 0 if none
+    pub wait_list: usize,
+    // parameters before priority inheritance, None if not boosted
+    pub pi_base: Option<Params>,
+    // Lock waiting for, and Locks held, 0 if none
+    pub blocked_lock: usize,
+    pub held: [usize; MAX_HELD],
+    // nesting of hardware interrupt handlers running on this stack
+    pub irq_depth: u8,
+    #[cfg(feature = "lockdep")]
+    pub lockdep: lockdep::Held,
     name_buf: [u8; 16],
 
     // page directory, 0 for kernel thread
@@ -114,6 +156,14 @@ pub struct PCB {
     // virtual memory pool, for user process
     v_pool: VPool,
     pub desc: [BlkDesc; DESC_CNT],
+
+    // virtual memory areas, for user process
+    vmas: VmaList,
+    // max size of user stack
+    pub stack_limit: usize,
+    // start and current end of user heap, see brk()
+    pub brk_start: usize,
+    pub brk: usize,
     magic: u32,
 }
 
@@ -130,15 +180,30 @@ impl Node for PCB {
 impl PCB {
     pub fn new(name: &str, priority: u8, off: usize) -> &'static mut Self {
         let p: &'static mut PCB = cst!(off);
-        let len = p.name_buf.len().min(name.as_bytes().len());
 
         p.stack = off + PCB_SIZE;
-        p.name_len = len as u8;
-        p.name_buf[..len].copy_from_slice(&name.as_bytes()[..len]);
+        p.set_name(name);
         p.pd = 0;
         p.ticks = priority;
         p.priority = priority;
+        p.level = sched::base_level(priority);
+        p.class = Class::Normal;
+        p.rt_priority = 0;
+        p.pid = 0;
+        p.parent = 0;
+        p.exit_code = 0;
+        p.timer = Timer::new(timeout::fire, off);
+        p.wait_list = 0;
+        p.pi_base = None;
+        p.blocked_lock = 0;
+        p.held = [0; MAX_HELD];
+        p.irq_depth = 0;
+        #[cfg(feature = "lockdep")]
+        {
+            p.lockdep = lockdep::Held::new();
+        }
         p.status = Ready;
+        p.vmas.init(0, 1);
         p.magic = STACK_MAGIC;
         p
     }
@@ -154,13 +219,22 @@ impl PCB {
         k_ctx.arg = arg as u32;
     }
 
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+
     #[inline]
     pub fn user(&self) -> bool {
         self.pd != 0
     }
 
+    pub fn elapsed_ticks(&self) -> u32 {
+        self.elapsed_ticks
+    }
+
+    /// registers saved by the last switch, valid only if not running
     #[inline]
-    fn kernel_ctx(&self) -> &'static mut KernelCtx {
+    pub fn kernel_ctx(&self) -> &'static mut KernelCtx {
         cst!(self.stack)
     }
 
@@ -177,6 +251,12 @@ impl PCB {
         self.magic != STACK_MAGIC
     }
 
+    pub fn set_name(&mut self, name: &str) {
+        let len = self.name_buf.len().min(name.as_bytes().len());
+        self.name_len = len as u8;
+        self.name_buf[..len].copy_from_slice(&name.as_bytes()[..len]);
+    }
+
     pub fn name(&self) -> &str {
         unsafe { core::str::from_utf8_unchecked(&self.name_buf[..self.name_len as usize]) }
     }
@@ -194,39 +274,77 @@ impl PCB {
         &mut self.v_pool
     }
 
+    pub fn vmas(&mut self) -> &mut VmaList {
+        &mut self.vmas
+    }
+
+    pub fn vmas_ref(&self) -> &VmaList {
+        &self.vmas
+    }
+
     pub fn page_dir(&self) -> Option<PageTable> {
         if self.pd == 0 {
             None
         } else {
-            Some(unsafe { core::slice::from_raw_parts_mut(self.pd as *mut _, PT_LEN) })
+            Some(page_dir(self.pd))
         }
     }
 }
 
+/// return addresses of saved ebp chain from ebp inside the kernel stack of pcb, innermost first
+pub fn frames(pcb: &PCB, mut ebp: usize) -> impl Iterator<Item=u32> {
+    let (lo, hi) = (pcb.off(), pcb.stack_off());
+    core::iter::from_fn(move || {
+        if ebp < lo || ebp + 8 > hi || ebp & 3 != 0 {
+            return None;
+        }
+        let ret = unsafe { *((ebp + 4) as *const u32) };
+        ebp = unsafe { *(ebp as *const u32) } as usize;
+        Some(ret)
+    })
+}
+
 // get current process control block
 pub fn current_pcb() -> &'static mut PCB {
     let p = cur_pcb!();
     cst!(p)
 }
 
+/// create a detached kernel thread, it is freed by reaper once it exits
 pub fn new_thread(rt: Routine, args: usize, name: &str, priority: u8) -> &'static mut PCB {
+    spawn(rt, args, name, priority, 0)
+}
+
+/// create a kernel thread the creator must join, or its pcb and pid are never freed
+pub fn new_joinable_thread(rt: Routine, args: usize, name: &str, priority: u8) -> &'static mut PCB {
+    spawn(rt, args, name, priority, current_pcb().pid)
+}
+
+fn spawn(rt: Routine, args: usize, name: &str, priority: u8, parent: usize) -> &'static mut PCB {
     let pcb_off = pg_alloc(Pool::KERNEL, 1, true).unwrap();
+    stat::set(v2p(pcb_off), Owner::Pcb);
     let pcb = PCB::new(name, priority, pcb_off);
     pcb.init(entry, rt, args);
-    ready().append(pcb);
+    pcb.parent = parent;
+    pid::alloc(pcb);
+    sched().enqueue(pcb, Enqueue::New);
     all().append(pcb);
     pcb
 }
 
 pub fn init() {
     data::init();
+    pid::init();
+    sched::init(sched::DEFAULT_POLICY);
 
     // add main thread to all list
     let main = current_pcb();
+    pid::alloc(main);
     all().append(main);
 
     // create idle thread
     unsafe { IDLE = new_thread(idle, 0, "idle", DEFAULT_PRIORITY / 2) as *const _ as usize };
+    exit::init();
 
     // register handler
     crate::int::register(0x20, handle_int);
@@ -244,35 +362,31 @@ fn handle_int(ctx: &'static mut IntCtx) {
         *t = t.unchecked_add(1);
         cur.elapsed_ticks = cur.elapsed_ticks.unchecked_add(1)
     }
+    crate::timer::run();
 
-    if cur.ticks != 0 {
-        cur.ticks -= 1;
-        return;
+    if sched().tick(cur) {
+        schedule("int");
     }
-    schedule("int");
 }
 
 // process scheduler
 pub fn schedule(reason: &str) {
     assert!(!crate::int::int_enabled(), "int enabled");
     let cur = current_pcb();
-    let off = cur.off();
-    let rd = ready();
+    let s = sched();
 
-    //c_println!("rd.is_empty() = {:?}", rd.is_empty());
     if cur.status == Status::Running {
-        assert!(!rd.raw_iter().any(|x| x == off), "thread in ready");
+        assert!(!s.contains(cur), "thread in ready");
         cur.status = Status::Ready;
-        cur.ticks = cur.priority;
-        rd.append(cur);
-    } 
+        s.enqueue(cur, Enqueue::Preempt);
+    }
 
-    if rd.is_empty() {
+    if s.is_empty() {
         // wake idle thread if ready is empty
         unblock(idle_thread());
     }
 
-    let n = rd.pop_head().unwrap();
+    let n = s.pick().unwrap();
     //c_println!("next = {}", n.name());
     n.status = Status::Running;
 
@@ -286,7 +400,7 @@ pub fn schedule(reason: &str) {
     let pd: usize = if n.user() { n.pd } else { PDE_START };
 
     unsafe {
-        asm!("mov cr3, {}", in(reg) pd);
+        asm!("mov cr3, {}", in(reg) cr3(pd));
     }
 
     if n.user() {
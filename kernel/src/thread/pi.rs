@@ -0,0 +1,152 @@
+use crate::int::{disable_int, set_int};
+use crate::thread::{PCB, Status};
+use crate::thread::rt::{Class, rt};
+use crate::thread::sched::{Enqueue, Scheduler};
+use crate::thread::sync::Lock;
+
+/// locks a thread can hold at once with priority inheritance
+pub const MAX_HELD: usize = 8;
+// longest chain of holders boosted by one waiter
+const MAX_CHAIN: usize = 16;
+
+/// scheduling parameters of a thread
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Params {
+    pub class: Class,
+    pub priority: u8,
+    pub rt_priority: u8,
+}
+
+impl Params {
+    pub fn of(pcb: &PCB) -> Self {
+        Self { class: pcb.class, priority: pcb.priority, rt_priority: pcb.rt_priority }
+    }
+
+    // larger runs first, real time threads before normal threads
+    fn key(&self) -> u32 {
+        match self.class {
+            Class::Normal => self.priority as u32,
+            _ => 256 + self.rt_priority as u32,
+        }
+    }
+}
+
+/// order of threads waiting for a lock, larger gets the lock first
+pub fn key(pcb: &PCB) -> u32 {
+    Params::of(pcb).key()
+}
+
+/// parameters set by set_scheduler, ignoring inherited priority
+pub fn base(pcb: &PCB) -> Params {
+    pcb.pi_base.unwrap_or_else(|| Params::of(pcb))
+}
+
+// change effective parameters, a ready thread is queued again by them
+fn apply(pcb: &mut PCB, p: Params) {
+    if Params::of(pcb) == p {
+        return;
+    }
+    let ready = pcb.status == Status::Ready;
+    if ready {
+        rt().remove(pcb);
+    }
+    pcb.class = p.class;
+    pcb.priority = p.priority;
+    pcb.rt_priority = p.rt_priority;
+    if ready {
+        rt().enqueue(pcb, Enqueue::New);
+    }
+}
+
+/// effective parameters are the most important of base and waiters of held locks
+pub fn recompute(pcb: &mut PCB) {
+    let old = disable_int();
+    let b = base(pcb);
+    let mut p = b;
+    for l in pcb.held.iter().filter(|x| **x != 0) {
+        let l: &Lock = cst!(*l);
+        for w in l.waiters() {
+            if Params::of(w).key() > p.key() {
+                p = Params::of(w);
+            }
+        }
+    }
+    pcb.pi_base = if p == b { None } else { Some(b) };
+    apply(pcb, p);
+    set_int(old);
+}
+
+/// set base parameters of pcb, the inherited priority is kept if higher
+pub fn set_base(pcb: &mut PCB, p: Params) {
+    let old = disable_int();
+    pcb.pi_base = Some(p);
+    recompute(pcb);
+    set_int(old);
+}
+
+/// cur is going to block on lock, boost the chain of holders to its priority
+/// called with interrupts disabled
+pub fn block_on(cur: &mut PCB, lock: &Lock) {
+    cur.blocked_lock = lock as *const _ as usize;
+    let p = Params::of(cur);
+    let mut l = lock;
+    for _ in 0..MAX_CHAIN {
+        let h: &mut PCB = match l.holder() {
+            Some(h) => cst!(h.off()),
+            None => return,
+        };
+        if Params::of(h).key() >= p.key() {
+            return;
+        }
+        if h.pi_base.is_none() {
+            h.pi_base = Some(Params::of(h));
+        }
+        apply(h, p);
+        if h.blocked_lock == 0 {
+            return;
+        }
+        l = cst!(h.blocked_lock);
+    }
+}
+
+/// cur stops waiting for lock, by acquiring it or timeout
+/// on timeout, the holders may not need the boost any more
+/// called with interrupts disabled
+pub fn unblock_from(cur: &mut PCB, lock: &Lock, acquired: bool) {
+    cur.blocked_lock = 0;
+    if acquired {
+        return;
+    }
+    let mut l = lock;
+    for _ in 0..MAX_CHAIN {
+        let h: &mut PCB = match l.holder() {
+            Some(h) => cst!(h.off()),
+            None => return,
+        };
+        recompute(h);
+        if h.blocked_lock == 0 {
+            return;
+        }
+        l = cst!(h.blocked_lock);
+    }
+}
+
+/// record lock is held by cur, locks beyond MAX_HELD do not pass priority
+/// cur inherits the priority of threads still waiting for lock
+pub fn acquired(cur: &mut PCB, lock: &Lock) {
+    if let Some(x) = cur.held.iter_mut().find(|x| **x == 0) {
+        *x = lock as *const _ as usize;
+    }
+    recompute(cur);
+}
+
+/// cur releases lock, drop the priority inherited from its waiters
+pub fn released(cur: &mut PCB, lock: &Lock) {
+    let old = disable_int();
+    let l = lock as *const _ as usize;
+    if let Some(x) = cur.held.iter_mut().find(|x| **x == l) {
+        *x = 0;
+    }
+    recompute(cur);
+    set_int(old);
+}
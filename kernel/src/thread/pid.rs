@@ -0,0 +1,88 @@
+use rlib::alloc_static;
+use rlib::link::LinkedList;
+
+use crate::err::SE;
+use crate::int::{disable_int, set_int};
+use crate::thread::{PCB, PCB_PADDING};
+
+/// pids are 1..=PID_MAX, 0 means no thread
+pub const PID_MAX: usize = 32768;
+/// reserved for the init process, never given by alloc
+pub const INIT_PID: usize = 1;
+const BUCKETS: usize = 64;
+
+/// hash table from pid to pcb, chained by pointers[4, 5] of pcb
+/// a thread stays in the table until reaper frees it, so a zombie can still be found by pid
+pub struct PidTable {
+    buckets: [LinkedList<PCB, PCB_PADDING>; BUCKETS],
+    // the last allocated pid
+    last: usize,
+}
+
+alloc_static!(PID_TABLE, pid_table, PidTable);
+
+// called by thread::init
+pub fn init() {
+    let t = pid_table();
+    for b in t.buckets.iter_mut() {
+        b.init(4, 5);
+    }
+    t.last = 0;
+}
+
+fn bucket(pid: usize) -> &'static mut LinkedList<PCB, PCB_PADDING> {
+    &mut pid_table().buckets[pid % BUCKETS]
+}
+
+fn lookup(pid: usize) -> Option<&'static mut PCB> {
+    bucket(pid).iter().find(|x| x.pid == pid)
+}
+
+/// give pcb the next free pid and add it to table
+/// pids increase monotonically, and the ones still in use are skipped after wraparound
+pub fn alloc(pcb: &mut PCB) {
+    let old = disable_int();
+    let t = pid_table();
+    loop {
+        t.last = if t.last == PID_MAX { 1 } else { t.last + 1 };
+        if t.last != INIT_PID && lookup(t.last).is_none() {
+            break;
+        }
+    }
+    pcb.pid = t.last;
+    bucket(pcb.pid).append(pcb);
+    set_int(old);
+}
+
+/// give pcb the pid of init
+pub fn alloc_init(pcb: &mut PCB) -> Result<(), SE> {
+    let old = disable_int();
+    let r = if lookup(INIT_PID).is_some() {
+        Err("init is running")
+    } else {
+        pcb.pid = INIT_PID;
+        bucket(INIT_PID).append(pcb);
+        Ok(())
+    };
+    set_int(old);
+    r
+}
+
+/// remove pcb from table, its pid can be reused
+pub fn free(pcb: &mut PCB) {
+    let old = disable_int();
+    bucket(pcb.pid).remove(pcb);
+    pcb.pid = 0;
+    set_int(old);
+}
+
+/// find live or dead thread by pid
+pub fn find(pid: usize) -> Option<&'static mut PCB> {
+    if pid == 0 || pid > PID_MAX {
+        return None;
+    }
+    let old = disable_int();
+    let r = lookup(pid);
+    set_int(old);
+    r
+}
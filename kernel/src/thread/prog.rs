@@ -0,0 +1,47 @@
+use crate::err::SE;
+use crate::thread::sync::Mutex;
+
+pub const MAX_PROGS: usize = 16;
+
+// user programs embedded by build.rs
+mod initrd {
+    include!(concat!(env!("OUT_DIR"), "/initrd.rs"));
+}
+
+type Prog = Option<(&'static str, &'static [u8])>;
+
+/// elf images of user programs by name, looked up by exec
+static PROGS: Mutex<[Prog; MAX_PROGS]> = Mutex::new([None; MAX_PROGS]);
+
+/// add program name with elf image, a registered name is replaced
+pub fn register(name: &'static str, image: &'static [u8]) -> Result<(), SE> {
+    let mut ps = PROGS.lock();
+    let i = ps.iter().position(|x| x.map(|x| x.0 == name).unwrap_or(false))
+        .or_else(|| ps.iter().position(|x| x.is_none()));
+    match i {
+        Some(i) => {
+            ps[i] = Some((name, image));
+            Ok(())
+        }
+        None => Err("too many programs"),
+    }
+}
+
+/// image of program name
+pub fn find(name: &[u8]) -> Option<&'static [u8]> {
+    PROGS.lock().iter().flatten().find(|x| x.0.as_bytes() == name).map(|x| x.1)
+}
+
+/// names and image sizes of registered programs
+pub fn list() -> impl Iterator<Item=(&'static str, usize)> {
+    let ps = *PROGS.lock();
+    ps.into_iter().flatten().map(|x| (x.0, x.1.len()))
+}
+
+/// register programs of initrd, return how many are registered
+pub fn load_initrd() -> Result<usize, SE> {
+    for (name, image) in initrd::IMAGES {
+        register(name, image)?;
+    }
+    Ok(initrd::IMAGES.len())
+}
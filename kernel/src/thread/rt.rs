@@ -0,0 +1,167 @@
+use rlib::alloc_static;
+use rlib::link::LinkedList;
+
+use crate::err::SE;
+use crate::thread::{PCB, PCB_PADDING, pi, ticks};
+use crate::thread::sched::{Enqueue, normal, Scheduler};
+
+/// scheduling class of a thread
+#[repr(u8)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Class {
+    // scheduled by the normal policy
+    Normal,
+    // runs until it blocks, yields or a higher real time thread is ready
+    Fifo,
+    // like fifo, but threads of the same priority share cpu by time slice
+    Rr,
+}
+
+/// static priorities of real time threads are 1..RT_PRIORITIES
+pub const RT_PRIORITIES: usize = 32;
+// time slice of Rr class
+const RR_QUANTUM: u8 = 100;
+// real time threads run at most RT_RUNTIME ticks in every RT_PERIOD ticks
+// the rest is left for normal threads, like sched_rt_runtime_us of linux
+const RT_PERIOD: u32 = 1000;
+const RT_RUNTIME: u32 = 950;
+
+/// real time class in front of the normal scheduler
+pub struct Rt {
+    queues: [LinkedList<PCB, PCB_PADDING>; RT_PRIORITIES],
+    period_start: u32,
+    // ticks used by real time threads in current period
+    used: u32,
+    throttled: bool,
+}
+
+alloc_static!(RT, rt_static, Rt);
+
+pub fn rt() -> &'static mut Rt {
+    rt_static()
+}
+
+// called by sched::init
+pub fn init() {
+    let r = rt();
+    for q in r.queues.iter_mut() {
+        q.init(2, 3);
+    }
+    r.period_start = *ticks();
+    r.used = 0;
+    r.throttled = false;
+}
+
+impl Rt {
+    fn highest(&self) -> Option<usize> {
+        (1..RT_PRIORITIES).rev().find(|p| !self.queues[*p].is_empty())
+    }
+
+    // real time threads can be picked
+    fn runnable(&self) -> Option<usize> {
+        if self.throttled { None } else { self.highest() }
+    }
+
+    // start a new period
+    fn account(&mut self) {
+        let now = *ticks();
+        if now.wrapping_sub(self.period_start) >= RT_PERIOD {
+            self.period_start = now;
+            self.used = 0;
+            self.throttled = false;
+        }
+    }
+}
+
+impl Scheduler for Rt {
+    fn name(&self) -> &'static str {
+        "rt"
+    }
+
+    fn enqueue(&mut self, pcb: &mut PCB, why: Enqueue) {
+        let q = &mut self.queues[pcb.rt_priority as usize];
+        match (pcb.class, why) {
+            (Class::Normal, _) => normal().enqueue(pcb, why),
+            // preempted thread runs first at its priority, unless its time slice expired
+            (Class::Fifo, Enqueue::Preempt) => q.push_head(pcb),
+            (Class::Rr, Enqueue::Preempt) if pcb.ticks != 0 => q.push_head(pcb),
+            _ => {
+                pcb.ticks = RR_QUANTUM;
+                q.append(pcb);
+            }
+        }
+    }
+
+    fn pick(&mut self) -> Option<&'static mut PCB> {
+        match self.runnable() {
+            Some(p) => self.queues[p].pop_head(),
+            None => normal().pick(),
+        }
+    }
+
+    fn tick(&mut self, cur: &mut PCB) -> bool {
+        self.account();
+
+        if cur.class == Class::Normal {
+            // real time thread always preempts normal thread
+            return self.runnable().is_some() || normal().tick(cur);
+        }
+
+        self.used += 1;
+        if self.used >= RT_RUNTIME {
+            self.throttled = true;
+            return true;
+        }
+
+        if self.highest().map(|p| p > cur.rt_priority as usize).unwrap_or(false) {
+            return true;
+        }
+
+        if cur.class == Class::Rr {
+            if cur.ticks != 0 {
+                cur.ticks -= 1;
+                return false;
+            }
+            return true;
+        }
+        false
+    }
+
+    fn contains(&self, pcb: &PCB) -> bool {
+        match pcb.class {
+            Class::Normal => normal().contains(pcb),
+            _ => self.queues[pcb.rt_priority as usize].raw_iter().any(|x| x == pcb.off()),
+        }
+    }
+
+    fn remove(&mut self, pcb: &mut PCB) {
+        match pcb.class {
+            Class::Normal => normal().remove(pcb),
+            _ => self.queues[pcb.rt_priority as usize].remove(pcb),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.runnable().is_none() && normal().is_empty()
+    }
+}
+
+/// set scheduling class and static priority of pcb
+/// priority is 1..RT_PRIORITIES for real time class, or priority of normal thread
+pub fn set_scheduler(pcb: &mut PCB, class: Class, priority: u8) -> Result<(), SE> {
+    if priority == 0 || (class != Class::Normal && priority as usize >= RT_PRIORITIES) {
+        return Err("invalid priority");
+    }
+
+    // a ready thread is queued again by its new class, inherited priority is kept if higher
+    let mut p = pi::base(pcb);
+    p.class = class;
+    if class == Class::Normal {
+        p.priority = priority;
+        p.rt_priority = 0;
+    } else {
+        p.rt_priority = priority;
+    }
+    pi::set_base(pcb, p);
+    Ok(())
+}
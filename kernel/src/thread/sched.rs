@@ -0,0 +1,222 @@
+use rlib::alloc_static;
+use rlib::link::LinkedList;
+
+use crate::thread::{PCB, PCB_PADDING, ticks};
+use crate::thread::data::ready;
+use crate::thread::rt;
+
+/// why a thread is put into ready queue
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Enqueue {
+    // created or forked
+    New,
+    // interrupted by timer
+    Preempt,
+    // gives up cpu by th_yield
+    Yield,
+    // returns from blocking, e.g. Semaphore::p
+    Wakeup,
+}
+
+/// policy of choosing the next thread, called with interrupt disabled
+pub trait Scheduler {
+    fn name(&self) -> &'static str;
+
+    fn enqueue(&mut self, pcb: &mut PCB, why: Enqueue);
+
+    /// remove and return the next thread to run
+    fn pick(&mut self) -> Option<&'static mut PCB>;
+
+    /// account a timer tick of running thread, return true if it should be preempted
+    fn tick(&mut self, cur: &mut PCB) -> bool;
+
+    fn contains(&self, pcb: &PCB) -> bool;
+
+    /// take a ready thread out of queue
+    fn remove(&mut self, pcb: &mut PCB);
+
+    fn is_empty(&self) -> bool;
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Policy {
+    RoundRobin,
+    Mlfq,
+}
+
+pub const DEFAULT_POLICY: Policy = Policy::Mlfq;
+
+static mut NORMAL: Option<&'static mut dyn Scheduler> = None;
+
+/// scheduler of all threads, real time threads run before normal threads
+pub fn sched() -> &'static mut dyn Scheduler {
+    rt::rt()
+}
+
+/// scheduler of normal threads
+pub fn normal() -> &'static mut dyn Scheduler {
+    unsafe { NORMAL.as_deref_mut().unwrap() }
+}
+
+// called before any thread is ready
+pub fn init(p: Policy) {
+    let s: &'static mut dyn Scheduler = match p {
+        Policy::RoundRobin => round_robin(),
+        Policy::Mlfq => {
+            let m = mlfq();
+            for l in m.levels.iter_mut() {
+                l.init(2, 3);
+            }
+            m.last_boost = *ticks();
+            m
+        }
+    };
+    unsafe { NORMAL = Some(s) };
+    rt::init();
+}
+
+/// round robin over one queue, priority is the number of ticks a thread runs
+pub struct RoundRobin;
+
+alloc_static!(ROUND_ROBIN, round_robin, RoundRobin);
+
+impl Scheduler for RoundRobin {
+    fn name(&self) -> &'static str {
+        "round robin"
+    }
+
+    fn enqueue(&mut self, pcb: &mut PCB, why: Enqueue) {
+        if why == Enqueue::Wakeup {
+            ready().push_head(pcb);
+            return;
+        }
+        pcb.ticks = pcb.priority;
+        ready().append(pcb);
+    }
+
+    fn pick(&mut self) -> Option<&'static mut PCB> {
+        ready().pop_head()
+    }
+
+    fn tick(&mut self, cur: &mut PCB) -> bool {
+        if cur.ticks != 0 {
+            cur.ticks -= 1;
+            return false;
+        }
+        true
+    }
+
+    fn contains(&self, pcb: &PCB) -> bool {
+        ready().raw_iter().any(|x| x == pcb.off())
+    }
+
+    fn remove(&mut self, pcb: &mut PCB) {
+        ready().remove(pcb);
+    }
+
+    fn is_empty(&self) -> bool {
+        ready().is_empty()
+    }
+}
+
+pub const LEVELS: usize = 8;
+// all threads go back to their base level periodically, so cpu bound threads never starve
+const BOOST_TICKS: u32 = 1000;
+
+/// highest level a thread of priority runs at, level 0 runs first
+/// priority 32 is level 3, priority 64 and above is level 0
+pub fn base_level(priority: u8) -> u8 {
+    (LEVELS - 1 - (priority as usize * LEVELS / 64).min(LEVELS - 1)) as u8
+}
+
+// ticks a thread runs at level before demoted, lower levels run longer
+fn quantum(level: u8) -> u8 {
+    (level + 1) * 8
+}
+
+/// multilevel feedback queue
+/// a thread is demoted when its quantum expires, and promoted when it wakes up from blocking
+/// a thread is preempted on tick if a higher level is not empty
+pub struct Mlfq {
+    levels: [LinkedList<PCB, PCB_PADDING>; LEVELS],
+    last_boost: u32,
+}
+
+alloc_static!(MLFQ, mlfq, Mlfq);
+
+impl Mlfq {
+    fn highest(&self) -> Option<usize> {
+        (0..LEVELS).find(|l| !self.levels[*l].is_empty())
+    }
+
+    // move every thread to its base level
+    fn boost(&mut self) {
+        for l in 1..LEVELS {
+            for _ in 0..self.levels[l].len() {
+                let p = self.levels[l].pop_head().unwrap();
+                p.level = base_level(p.priority);
+                self.levels[p.level as usize].append(p);
+            }
+        }
+    }
+}
+
+impl Scheduler for Mlfq {
+    fn name(&self) -> &'static str {
+        "mlfq"
+    }
+
+    fn enqueue(&mut self, pcb: &mut PCB, why: Enqueue) {
+        let base = base_level(pcb.priority);
+        match why {
+            Enqueue::New => pcb.level = base,
+            // io bound thread goes up a level
+            Enqueue::Wakeup => pcb.level = pcb.level.saturating_sub(1).max(base),
+            // preempted by a higher level, run the rest of quantum first
+            Enqueue::Preempt if pcb.ticks != 0 => {
+                self.levels[pcb.level as usize].push_head(pcb);
+                return;
+            }
+            _ => {}
+        }
+        pcb.ticks = quantum(pcb.level);
+        self.levels[pcb.level as usize].append(pcb);
+    }
+
+    fn pick(&mut self) -> Option<&'static mut PCB> {
+        let l = self.highest()?;
+        self.levels[l].pop_head()
+    }
+
+    fn tick(&mut self, cur: &mut PCB) -> bool {
+        let now = *ticks();
+        if now.wrapping_sub(self.last_boost) >= BOOST_TICKS {
+            self.last_boost = now;
+            self.boost();
+            cur.level = base_level(cur.priority);
+        }
+
+        if cur.ticks != 0 {
+            cur.ticks -= 1;
+            return self.highest().map(|l| l < cur.level as usize).unwrap_or(false);
+        }
+
+        // quantum expired
+        if (cur.level as usize) < LEVELS - 1 {
+            cur.level += 1;
+        }
+        true
+    }
+
+    fn contains(&self, pcb: &PCB) -> bool {
+        self.levels.iter().any(|l| l.raw_iter().any(|x| x == pcb.off()))
+    }
+
+    fn remove(&mut self, pcb: &mut PCB) {
+        self.levels[pcb.level as usize].remove(pcb);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.highest().is_none()
+    }
+}
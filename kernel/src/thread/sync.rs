@@ -1,11 +1,22 @@
+use core::cell::{Cell, UnsafeCell};
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU8, Ordering};
+
 use rlib::link::LinkedList;
 
 use crate::{c_println, print, println};
-use crate::int::{disable_int, set_int};
+use crate::int::{disable_int, in_interrupt, int_enabled, set_int};
 use crate::thread::{current_pcb, PCB, schedule, Status, ticks};
-use crate::thread::data::{all, ready};
+use crate::thread::data::all;
+#[cfg(feature = "lockdep")]
+use crate::thread::lockdep;
+use crate::thread::pi;
+use crate::thread::sched::{Enqueue, sched};
+use crate::thread::timeout;
 use crate::thread::PCB_PADDING;
-use crate::timer::MIL_SECONDS_PER_INT;
+use crate::timer::{expired, mils_to_ticks};
 
 #[repr(C)]
 pub struct SpinLock {
@@ -13,31 +24,59 @@ pub struct SpinLock {
 }
 
 impl SpinLock {
+    pub const fn new() -> Self {
+        Self { data: 0 }
+    }
+
+    #[track_caller]
     pub fn lock(&self) {
         let p = self as *const _ as usize;
+        #[cfg(feature = "lockdep")]
+        let key = lockdep::Key::of(None);
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(key);
         unsafe {
             asm!("2:", "xchg eax, [{0}]", "test eax, eax", "jnz 2b", in(reg) p, in("eax") 1)
         }
+        #[cfg(feature = "lockdep")]
+        lockdep::acquired(p, key);
     }
 
     pub fn unlock(&self) {
         let p = self as *const _ as usize;
+        #[cfg(feature = "lockdep")]
+        lockdep::release(p);
         unsafe {
             asm!("xchg eax, [{0}]", in(reg) p, in("eax") 0);
         }
     }
 }
 
-pub fn sleep_ticks(t: usize) {
-    let start = *ticks();
-    while *ticks() - start < t as u32 {
-        th_yield();
+/// panic if current context cannot block, called by primitives that may sleep
+#[track_caller]
+pub fn might_sleep() {
+    assert!(!in_interrupt(), "sleeping in interrupt handler");
+}
+
+/// block current thread until ticks reach deadline, the idle thread runs meanwhile
+pub fn sleep_until(deadline: u32) {
+    might_sleep();
+    let old = disable_int();
+    let cur = current_pcb();
+    // a sleeper woken by unblock before its deadline sleeps again
+    while !expired(deadline) {
+        timeout::add(cur, deadline, None);
+        block(Status::Waiting);
     }
+    set_int(old);
+}
+
+pub fn sleep_ticks(t: usize) {
+    sleep_until((*ticks()).wrapping_add(t as u32));
 }
 
 pub fn sleep_mils(t: u32) {
-    let ticks = t / MIL_SECONDS_PER_INT;
-    sleep_ticks(ticks as usize);
+    sleep_ticks(mils_to_ticks(t) as usize);
 }
 
 #[repr(C)]
@@ -51,6 +90,8 @@ pub struct Lock {
     holder: Option<&'static PCB>,
     sem: Semaphore,
     repeats: u32,
+    // shown in reports of lockdep
+    name: Option<&'static str>,
 }
 
 pub struct Guard {
@@ -80,21 +121,29 @@ impl Guard {
 pub fn th_yield() {
     let cur = current_pcb();
     let old = disable_int();
-    assert!(!ready().raw_iter().any(|x| x == cur.off()), "cur shouldn't in ready");
-    let rd = ready();
-    ready().append(cur);
+    assert!(!sched().contains(cur), "cur shouldn't in ready");
     cur.status = Status::Ready;
+    sched().enqueue(cur, Enqueue::Yield);
     schedule("yield");
     set_int(old);
 }
 
+// waiter list initialized on first use, by then the owner must not move any more
+const fn waiters() -> LinkedList<PCB, PCB_PADDING> {
+    LinkedList { prev_i: 0, next_i: 0, head: 0, tail: 0, padding: [0; PCB_PADDING], ph: PhantomData }
+}
+
 impl Lock {
-    pub fn new(off: usize, len: usize) -> &'static mut Self {
-        let lock_len = core::mem::size_of::<Lock>();
-        assert!(len >= lock_len);
-        let r: &'static mut Self = cst!(off);
-        r.init();
-        r
+    pub const fn new() -> Self {
+        Self { holder: None, sem: Semaphore::new(1), repeats: 0, name: None }
+    }
+
+    pub const fn named(name: &'static str) -> Self {
+        Self { holder: None, sem: Semaphore::new(1), repeats: 0, name: Some(name) }
+    }
+
+    pub fn set_name(&mut self, name: &'static str) {
+        self.name = Some(name);
     }
 
     pub fn init(&mut self) {
@@ -105,17 +154,87 @@ impl Lock {
     }
 
     #[no_mangle]
+    #[track_caller]
     pub fn lock(&mut self) -> Guard {
+        might_sleep();
         let cur = current_pcb();
 
         if self.holder.is_some() && self.holder.as_ref().unwrap().off() == cur.off() {
             self.repeats += 1;
             return Guard { lock: self as *const _ as usize };
         }
+        #[cfg(feature = "lockdep")]
+        let key = lockdep::Key::of(self.name);
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(key);
+        let old = disable_int();
+        if self.holder.is_some() {
+            pi::block_on(cur, self);
+        }
         self.sem.p();
+        pi::unblock_from(cur, self, true);
+        #[cfg(feature = "lockdep")]
+        lockdep::acquired(self as *const _ as usize, key);
+        self.acquire(cur);
+        set_int(old);
+        return Guard { lock: self as *const _ as usize };
+    }
+
+    // called with interrupts disabled after sem is taken
+    fn acquire(&mut self, cur: &'static mut PCB) {
+        pi::acquired(cur, self);
         self.holder = Some(cur);
         self.repeats += 1;
-        return Guard { lock: self as *const _ as usize };
+    }
+
+    pub fn holder(&self) -> Option<&'static PCB> {
+        self.holder
+    }
+
+    /// threads blocked on this lock
+    pub fn waiters(&self) -> impl Iterator<Item=&'static mut PCB> {
+        let w = &self.sem.waiters;
+        (w.head != 0).then(|| w.iter()).into_iter().flatten()
+    }
+
+    /// lock without blocking, None if held by another thread
+    #[track_caller]
+    pub fn try_lock(&mut self) -> Option<Guard> {
+        self.lock_timeout(0)
+    }
+
+    /// block at most mils milliseconds, None on timeout
+    #[track_caller]
+    pub fn lock_timeout(&mut self, mils: u32) -> Option<Guard> {
+        if mils != 0 {
+            might_sleep();
+        }
+        let cur = current_pcb();
+
+        if self.holder.is_some() && self.holder.as_ref().unwrap().off() == cur.off() {
+            self.repeats += 1;
+            return Some(Guard { lock: self as *const _ as usize });
+        }
+        #[cfg(feature = "lockdep")]
+        let key = lockdep::Key::of(self.name);
+        // a trylock cannot deadlock, only locks taken after it are ordered
+        #[cfg(feature = "lockdep")]
+        if mils != 0 {
+            lockdep::acquire(key);
+        }
+        let old = disable_int();
+        if self.holder.is_some() {
+            pi::block_on(cur, self);
+        }
+        let r = self.sem.p_timeout(mils);
+        pi::unblock_from(cur, self, r);
+        if r {
+            #[cfg(feature = "lockdep")]
+            lockdep::acquired(self as *const _ as usize, key);
+            self.acquire(cur);
+        }
+        set_int(old);
+        r.then(|| Guard { lock: self as *const _ as usize })
     }
 
     #[no_mangle]
@@ -133,18 +252,37 @@ impl Lock {
         }
 
         assert_eq!(self.repeats, 1, "repeats != 0");
+        let old = disable_int();
         self.holder = None;
         self.repeats = 0;
-        self.sem.v();
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self as *const _ as usize);
+        // give up inherited priority before the waiter runs
+        pi::released(cur, self);
+        self.sem.v_max(pi::key);
+        set_int(old);
     }
 }
 
 impl Semaphore {
+    pub const fn new(value: u32) -> Self {
+        Self { value, waiters: waiters() }
+    }
+
+    // called with interrupts disabled
+    fn lazy_init(&mut self) {
+        if self.waiters.head == 0 {
+            self.waiters.init(2, 3);
+        }
+    }
+
     // p operation
     #[inline(never)]
     #[no_mangle]
     pub fn p(&mut self) {
+        might_sleep();
         let old = disable_int();
+        self.lazy_init();
         let cur = current_pcb();
         debug!("{}: p()", cur.name());
         while self.value == 0 {
@@ -162,10 +300,35 @@ impl Semaphore {
         set_int(old);
     }
 
+    /// p operation giving up after mils milliseconds, return false on timeout
+    /// the timer interrupt removes the blocked thread from waiters
+    pub fn p_timeout(&mut self, mils: u32) -> bool {
+        if mils != 0 {
+            might_sleep();
+        }
+        let old = disable_int();
+        self.lazy_init();
+        let cur = current_pcb();
+        let deadline = (*ticks()).wrapping_add(mils_to_ticks(mils));
+        while self.value == 0 {
+            if expired(deadline) {
+                set_int(old);
+                return false;
+            }
+            self.waiters.append(cur);
+            timeout::add(cur, deadline, Some(&self.waiters));
+            block(Status::Blocked);
+        }
+        self.value -= 1;
+        set_int(old);
+        true
+    }
+
     #[inline(never)]
     #[no_mangle]
     pub fn v(&mut self) {
         let old = disable_int();
+        self.lazy_init();
 
         let cur = current_pcb();
         debug!("{}: v()", cur.name());
@@ -179,6 +342,26 @@ impl Semaphore {
         debug!("{}: v() success", cur.name());
         set_int(old);
     }
+
+    /// v operation waking the waiter of the largest key, the earliest of them on ties
+    pub fn v_max<F: Fn(&PCB) -> u32>(&mut self, key: F) {
+        let old = disable_int();
+        self.lazy_init();
+
+        let mut top: Option<&'static mut PCB> = None;
+        for x in self.waiters.iter() {
+            if top.as_ref().map_or(true, |t| key(x) > key(t)) {
+                top = Some(x);
+            }
+        }
+        if let Some(x) = top {
+            self.waiters.remove(x);
+            unblock(x);
+        }
+
+        self.value += 1;
+        set_int(old);
+    }
 }
 
 pub fn block(status: Status) {
@@ -192,7 +375,6 @@ pub fn block(status: Status) {
 
 pub fn unblock(pcb: &'static mut PCB) {
     let old = disable_int();
-    let off = { pcb.off() };
     assert!(
         pcb.status == Status::Blocked
             || pcb.status == Status::Waiting
@@ -204,13 +386,419 @@ pub fn unblock(pcb: &'static mut PCB) {
         return;
     }
 
-    let rd = ready();
-    assert!(
-        !rd.raw_iter().any(|x| x == off),
-        "target thread not blocked"
-    );
+    timeout::cancel(pcb);
+    assert!(!sched().contains(pcb), "target thread not blocked");
     pcb.status = Status::Ready;
-    rd.push_head(pcb);
+    sched().enqueue(pcb, Enqueue::Wakeup);
     set_int(old);
 }
 
+
+/// spin lock owning its data, interrupts are disabled while held
+/// so it can be shared with interrupt handlers, never sleep with it held
+pub struct IrqSpinLock<T> {
+    lock: SpinLock,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for IrqSpinLock<T> {}
+
+unsafe impl<T: Send> Sync for IrqSpinLock<T> {}
+
+pub struct IrqSpinLockGuard<'a, T> {
+    l: &'a IrqSpinLock<T>,
+    // interrupt flag before lock
+    int: bool,
+}
+
+impl<T> IrqSpinLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self { lock: SpinLock::new(), data: UnsafeCell::new(data) }
+    }
+
+    #[track_caller]
+    pub fn lock(&self) -> IrqSpinLockGuard<T> {
+        let int = disable_int();
+        self.lock.lock();
+        IrqSpinLockGuard { l: self, int }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+impl<T> Deref for IrqSpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.l.data.get() }
+    }
+}
+
+impl<T> DerefMut for IrqSpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.l.data.get() }
+    }
+}
+
+impl<T> Drop for IrqSpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.l.lock.unlock();
+        set_int(self.int);
+    }
+}
+
+/// sleeping lock owning its data, not recursive
+/// it can be in a static, but must not be moved after the first lock
+pub struct Mutex<T> {
+    sem: UnsafeCell<Semaphore>,
+    // pcb of holder, 0 if unlocked
+    holder: Cell<usize>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+pub struct MutexGuard<'a, T> {
+    m: &'a Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self { sem: UnsafeCell::new(Semaphore::new(1)), holder: Cell::new(0), data: UnsafeCell::new(data) }
+    }
+
+    /// block until the mutex is free
+    pub fn lock(&self) -> MutexGuard<T> {
+        might_sleep();
+        let cur = current_pcb();
+        assert_ne!(self.holder.get(), cur.off(), "mutex locked twice by {}", cur.name());
+        unsafe { &mut *self.sem.get() }.p();
+        self.holder.set(cur.off());
+        MutexGuard { m: self }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.holder.get() != 0
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    fn unlock(&self) {
+        assert_eq!(self.holder.get(), current_pcb().off(), "unlock mutex held by another thread");
+        self.holder.set(0);
+        unsafe { &mut *self.sem.get() }.v();
+    }
+}
+
+impl<'a, T> MutexGuard<'a, T> {
+    /// the mutex of guard, to lock it again after the guard is consumed by Condvar::wait
+    pub fn mutex(&self) -> &'a Mutex<T> {
+        self.m
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.m.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.m.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.m.unlock();
+    }
+}
+
+/// threads waiting for a condition protected by a Mutex
+pub struct Condvar {
+    waiters: UnsafeCell<LinkedList<PCB, PCB_PADDING>>,
+}
+
+unsafe impl Send for Condvar {}
+
+unsafe impl Sync for Condvar {}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self { waiters: UnsafeCell::new(waiters()) }
+    }
+
+    // called with interrupts disabled
+    fn waiters(&self) -> &mut LinkedList<PCB, PCB_PADDING> {
+        let w = unsafe { &mut *self.waiters.get() };
+        if w.head == 0 {
+            w.init(2, 3);
+        }
+        w
+    }
+
+    /// unlock the mutex and block until notified, the mutex is locked again before return
+    /// a notify between unlock and block is not lost since interrupts are disabled
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        might_sleep();
+        let m = guard.mutex();
+        let old = disable_int();
+        self.waiters().append(current_pcb());
+        drop(guard);
+        block(Status::Blocked);
+        set_int(old);
+        m.lock()
+    }
+
+    /// wait until f returns false
+    pub fn wait_while<'a, T, F: FnMut(&mut T) -> bool>(&self, mut guard: MutexGuard<'a, T>, mut f: F) -> MutexGuard<'a, T> {
+        while f(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// wake up the thread waiting longest
+    pub fn notify_one(&self) {
+        let old = disable_int();
+        if let Some(x) = self.waiters().pop_head() {
+            unblock(x);
+        }
+        set_int(old);
+    }
+
+    pub fn notify_all(&self) {
+        let old = disable_int();
+        while let Some(x) = self.waiters().pop_head() {
+            unblock(x);
+        }
+        set_int(old);
+    }
+}
+
+/// sleeping reader writer lock owning its data, waiting writers go before new readers
+/// it can be in a static, but must not be moved after the first lock
+pub struct RwLock<T> {
+    state: UnsafeCell<RwState>,
+    data: UnsafeCell<T>,
+}
+
+struct RwState {
+    readers: usize,
+    writer: bool,
+    read_waiters: LinkedList<PCB, PCB_PADDING>,
+    write_waiters: LinkedList<PCB, PCB_PADDING>,
+}
+
+unsafe impl<T: Send> Send for RwLock<T> {}
+
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+pub struct RwLockReadGuard<'a, T> {
+    l: &'a RwLock<T>,
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    l: &'a RwLock<T>,
+}
+
+impl<T> RwLock<T> {
+    pub const fn new(data: T) -> Self {
+        let state = RwState { readers: 0, writer: false, read_waiters: waiters(), write_waiters: waiters() };
+        Self { state: UnsafeCell::new(state), data: UnsafeCell::new(data) }
+    }
+
+    // called with interrupts disabled
+    fn state(&self) -> &mut RwState {
+        let s = unsafe { &mut *self.state.get() };
+        if s.read_waiters.head == 0 {
+            s.read_waiters.init(2, 3);
+            s.write_waiters.init(2, 3);
+        }
+        s
+    }
+
+    /// block until no writer holds or waits
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        might_sleep();
+        let old = disable_int();
+        loop {
+            let s = self.state();
+            if !s.writer && s.write_waiters.is_empty() {
+                s.readers += 1;
+                break;
+            }
+            s.read_waiters.append(current_pcb());
+            block(Status::Blocked);
+        }
+        set_int(old);
+        RwLockReadGuard { l: self }
+    }
+
+    /// block until no reader or writer holds
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        might_sleep();
+        let old = disable_int();
+        loop {
+            let s = self.state();
+            if !s.writer && s.readers == 0 {
+                s.writer = true;
+                break;
+            }
+            s.write_waiters.append(current_pcb());
+            block(Status::Blocked);
+        }
+        set_int(old);
+        RwLockWriteGuard { l: self }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    // wake a writer, or all readers if no writer waits
+    fn wake(s: &mut RwState) {
+        if let Some(x) = s.write_waiters.pop_head() {
+            unblock(x);
+            return;
+        }
+        while let Some(x) = s.read_waiters.pop_head() {
+            unblock(x);
+        }
+    }
+
+    fn read_unlock(&self) {
+        let old = disable_int();
+        let s = self.state();
+        s.readers -= 1;
+        if s.readers == 0 {
+            Self::wake(s);
+        }
+        set_int(old);
+    }
+
+    fn write_unlock(&self) {
+        let old = disable_int();
+        let s = self.state();
+        s.writer = false;
+        Self::wake(s);
+        set_int(old);
+    }
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.l.data.get() }
+    }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.l.read_unlock();
+    }
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.l.data.get() }
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.l.data.get() }
+    }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.l.write_unlock();
+    }
+}
+
+const INCOMPLETE: u8 = 0;
+const RUNNING: u8 = 1;
+const COMPLETE: u8 = 2;
+
+/// run a function once, works before threads and locks are initialized
+pub struct Once {
+    state: AtomicU8,
+}
+
+impl Once {
+    pub const fn new() -> Self {
+        Self { state: AtomicU8::new(INCOMPLETE) }
+    }
+
+    /// run f if no one did, otherwise wait until the first call finishes
+    pub fn call_once<F: FnOnce()>(&self, f: F) {
+        if self.state.compare_exchange(INCOMPLETE, RUNNING, Ordering::Acquire, Ordering::Acquire).is_ok() {
+            f();
+            self.state.store(COMPLETE, Ordering::Release);
+            return;
+        }
+        // the running call is in another thread, it cannot be scheduled with interrupts disabled
+        while !self.is_completed() {
+            assert!(int_enabled(), "Once is running with interrupts disabled");
+            th_yield();
+        }
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == COMPLETE
+    }
+}
+
+/// value initialized by f on first access
+pub struct Lazy<T, F = fn() -> T> {
+    once: Once,
+    f: Cell<Option<F>>,
+    data: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send + Sync, F: Send> Sync for Lazy<T, F> {}
+
+impl<T, F> Lazy<T, F> {
+    pub const fn new(f: F) -> Self {
+        Self { once: Once::new(), f: Cell::new(Some(f)), data: UnsafeCell::new(MaybeUninit::uninit()) }
+    }
+}
+
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+    pub fn force(this: &Self) -> &T {
+        this.once.call_once(|| {
+            let f = this.f.take().unwrap();
+            unsafe { (*this.data.get()).as_mut_ptr().write(f()) };
+        });
+        unsafe { &*(*this.data.get()).as_ptr() }
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        Self::force(self)
+    }
+}
@@ -0,0 +1,34 @@
+use rlib::link::LinkedList;
+
+use crate::int::{disable_int, set_int};
+use crate::thread::{PCB, PCB_PADDING, Status};
+use crate::thread::sync::unblock;
+
+/// wake pcb at deadline if it is still blocked or waiting, list is the waiters list it blocks on, if any
+/// the thread is removed from list on timeout, caller must disable interrupts and block after this
+pub fn add(pcb: &mut PCB, deadline: u32, list: Option<&LinkedList<PCB, PCB_PADDING>>) {
+    pcb.wait_list = list.map_or(0, |x| x as *const _ as usize);
+    pcb.timer.start_at(deadline);
+}
+
+/// remove pending timeout of pcb, called when it is woken up by others
+pub fn cancel(pcb: &mut PCB) {
+    let old = disable_int();
+    pcb.timer.cancel();
+    pcb.wait_list = 0;
+    set_int(old);
+}
+
+// callback of pcb timer, arg is the pcb
+pub fn fire(arg: usize) {
+    let x: &mut PCB = cst!(arg);
+    if x.wait_list != 0 {
+        let list: &mut LinkedList<PCB, PCB_PADDING> = cst!(x.wait_list);
+        list.remove(x);
+    }
+    assert!(
+        x.status == Status::Blocked || x.status == Status::Waiting,
+        "timeout of thread {} not blocked", x.name()
+    );
+    unblock(x);
+}
@@ -2,13 +2,18 @@ use rlib::bitmap::Bitmap;
 use rlib::div_up;
 
 use crate::{c_println, Pool, println, v2p};
-use crate::asm::{SELECTOR_U_CODE, SELECTOR_U_DATA};
+use crate::asm::{SELECTOR_K_DATA, SELECTOR_U_CODE, SELECTOR_U_DATA};
+use crate::err::SE;
 use crate::int::{disable_int, set_int};
-use crate::mem::{fill_zero, PAGE_SIZE, pg_alloc, PT_LEN};
-use crate::mem::alloc::alloc_one;
-use crate::mem::page::{DEFAULT_PT_ATTR, OS_MEM_OFF, page_table, PageTableEntry, PDE_START, USER_V_START};
-use crate::thread::{current_pcb, PCB, Routine};
-use crate::thread::data::{all, ready};
+use crate::mem::{fill_zero, frame, k_lock, kernel_pool, PAGE_SIZE, pg_alloc, pg_free, shm, swap, u_lock, user_pool};
+use crate::mem::alloc::{PAlloc, reserve};
+use crate::mem::fault::DEFAULT_STACK_LIMIT;
+use crate::mem::stat::{self, Owner};
+use crate::mem::page::{cr3, DEFAULT_PT_ATTR, large_page_size, new_pd, OS_MEM_OFF, page_table, pd_pages, PDE_START, PG_RW, pt_len, USER_BRK_START, USER_V_START, VirtualAddress};
+use crate::mem::vma::{self, Vma, VM_GROWS_DOWN, VM_READ, VM_SHARED, VM_WRITE, VmaKind};
+use crate::thread::{current_pcb, PCB, PCB_PAGES, pid, Routine};
+use crate::thread::data::all;
+use crate::thread::sched::{Enqueue, sched};
 use crate::thread::reg::{IntCtx, KernelCtx};
 
 const USER_PAGES: usize = 1;
@@ -19,36 +24,36 @@ const USER_E_FLAGS: u32 = (1 << 1) | (1 << 9);
 pub extern "C" fn entry(rt: Routine, args: usize) {
     let cur = current_pcb();
     cur.stack += core::mem::size_of::<KernelCtx>();
-    let ctx = cur.int_ctx();
-    fill_zero(cur.stack, core::mem::size_of::<IntCtx>());
-    ctx.ds = SELECTOR_U_DATA as u32;
-    ctx.ss = ctx.ds;
-    ctx.es = ctx.ss;
-    ctx.eip = rt as usize as u32;
-    ctx.cs = SELECTOR_U_CODE as u32;
-    ctx.esp = alloc_one(Pool::USER, OS_MEM_OFF - PAGE_SIZE, true).unwrap() as u32;
-    ctx.e_flags = USER_E_FLAGS;
-    ctx.esp += (PAGE_SIZE * USER_PAGES) as u32;
-    ctx.esp -= 4;
+    // stack pages are allocated on first touch
+    user_ctx(cur.int_ctx(), rt as usize, OS_MEM_OFF - 8);
     unsafe {
-        let top: *mut u32 = ctx.esp as usize as *mut _;
+        let top: *mut u32 = (OS_MEM_OFF - 4) as *mut _;
         *top = args as u32;
     }
-    ctx.esp -= 4;
 
     unsafe { asm!("mov esp, {0}", "jmp {1}", in(reg) cur.stack, in(reg) crate::asm::int_exit()); }
 }
 
-pub fn create(rt: Routine, args: usize, name: &str, priority: u8) {
-    let pcb_off = pg_alloc(Pool::KERNEL, 1, true).unwrap();
-    let pcb = PCB::new(name, priority, pcb_off);
-    pcb.init(entry, rt, args);
+/// fill interrupt context to enter user mode at eip with stack esp
+pub fn user_ctx(ctx: &mut IntCtx, eip: usize, esp: usize) {
+    fill_zero(ctx as *mut _ as usize, core::mem::size_of::<IntCtx>());
+    ctx.ds = SELECTOR_U_DATA as u32;
+    ctx.ss = ctx.ds;
+    ctx.es = ctx.ss;
+    ctx.eip = eip as u32;
+    ctx.cs = SELECTOR_U_CODE as u32;
+    ctx.e_flags = USER_E_FLAGS;
+    ctx.esp = esp as u32;
+}
 
+/// initialize virtual pool, arena and page directory of user process
+/// nothing is left allocated on failure
+pub fn init_space(pcb: &mut PCB) -> Result<(), SE> {
     // initialize v start
     pcb.v_pool.v_start = USER_V_START;
     let bits_bytes = (OS_MEM_OFF - USER_V_START) / PAGE_SIZE / 8;
     let p = div_up!(bits_bytes, PAGE_SIZE);
-    let bit_map = pg_alloc(Pool::KERNEL, p, true).unwrap();
+    let bit_map = pg_alloc(Pool::KERNEL, p, true)?;
     pcb.v_pool.bitmap = unsafe {
         core::slice::from_raw_parts_mut(bit_map as *mut _, p * PAGE_SIZE)
     };
@@ -56,15 +61,206 @@ pub fn create(rt: Routine, args: usize, name: &str, priority: u8) {
     crate::mem::arena::init_descs(&mut pcb.desc);
 
     // create page directory
-    pcb.pd = v2p(pg_alloc(Pool::KERNEL, 1, true).unwrap());
+    pcb.pd = match new_pd() {
+        Ok(x) => x,
+        Err(e) => {
+            pg_free(bit_map, p);
+            return Err(e);
+        }
+    };
+    Ok(())
+}
+
+/// free address space of a dead process, called by reaper, or of current process by exec
+/// frames, swap slots and page tables are found by walking its page directory
+pub fn free_space(pcb: &mut PCB) {
+    let lk = u_lock();
+    let _gd = lk.map(|x| x.lock());
+    let klk = k_lock();
+    let _kgd = klk.map(|x| x.lock());
+
+    shm::release(pcb);
+    for x in pcb.vmas_ref().iter() {
+        vma::remove(pcb, x);
+    }
+
     let pd = pcb.page_dir().unwrap();
-    pd.copy_from_slice(page_table(PDE_START));
-    // loopback page table entry
-    pd[PT_LEN - 1] = PageTableEntry::new(pcb.pd, DEFAULT_PT_ATTR);
+    let up = user_pool();
+    let kp = kernel_pool();
+    for i in USER_V_START.pde_i()..OS_MEM_OFF.pde_i() {
+        if !pd[i].exists() {
+            continue;
+        }
+        for e in pd[i].sub_table().iter() {
+            if let Some(slot) = e.swap_slot() {
+                swap::put(slot);
+                continue;
+            }
+            // shared frame is freed by its last mapping
+            if e.exists() && frame::put(e.addr()) {
+                up.remove(e.addr());
+                up.avl_pages += 1;
+            }
+        }
+        kp.remove(pd[i].addr());
+        kp.avl_pages += 1;
+    }
+
+    // kernel half of page directory is shared, only the directory itself is freed
+    // current process by exec leaves its directory first
+    let old = disable_int();
+    if pcb.off() == current_pcb().off() {
+        unsafe { asm!("mov cr3, {}", in(reg) cr3(PDE_START)) };
+    }
+    for i in 0..pd_pages() {
+        kp.remove(pcb.pd + i * PAGE_SIZE);
+    }
+    kp.avl_pages += pd_pages();
+    pcb.pd = 0;
+    set_int(old);
+
+    let bits = pcb.v_pool.bitmap.as_ptr() as usize;
+    let pages = pcb.v_pool.bitmap.len() / PAGE_SIZE;
+    pg_free(bits, pages);
+}
 
+/// create user stack below OS_MEM_OFF, it grows downward on page fault
+pub fn init_stack(pcb: &mut PCB) -> Result<&'static mut Vma, SE> {
+    let stack = OS_MEM_OFF - USER_PAGES * PAGE_SIZE;
+    let x = vma::add(pcb, stack, OS_MEM_OFF, VM_READ | VM_WRITE | VM_GROWS_DOWN, VmaKind::Stack)?;
+    reserve(pcb, stack, USER_PAGES)?;
+    pcb.stack_limit = DEFAULT_STACK_LIMIT;
+    Ok(x)
+}
+
+pub fn create(rt: Routine, args: usize, name: &str, priority: u8) {
+    let pcb_off = pg_alloc(Pool::KERNEL, 1, true).unwrap();
+    stat::set(v2p(pcb_off), Owner::Pcb);
+    let pcb = PCB::new(name, priority, pcb_off);
+    pcb.init(entry, rt, args);
+    pcb.parent = current_pcb().pid;
+    pid::alloc(pcb);
+    init_space(pcb).unwrap();
+
+    init_stack(pcb).unwrap();
+
+    // heap is created on first brk()
+    pcb.brk_start = USER_BRK_START;
+    pcb.brk = USER_BRK_START;
 
     let old = disable_int();
-    ready().append(pcb);
+    sched().enqueue(pcb, Enqueue::New);
     all().append(pcb);
     set_int(old);
-}
\ No newline at end of file
+}
+
+/// child of fork or spawned process returns to user space with the interrupt context on its kernel stack
+pub extern "C" fn fork_ret() {
+    let cur = current_pcb();
+    cur.stack += core::mem::size_of::<KernelCtx>();
+    unsafe { asm!("mov esp, {0}", "jmp {1}", in(reg) cur.stack, in(reg) crate::asm::int_exit()); }
+}
+
+/// clone current process, ctx is the syscall context of parent
+/// return pid of the child to parent, the child returns 0
+pub fn fork(ctx: &IntCtx) -> Result<usize, SE> {
+    let parent = current_pcb();
+    let pcb_off = pg_alloc(Pool::KERNEL, 1, true)?;
+    stat::set(v2p(pcb_off), Owner::Pcb);
+    let child = PCB::new(parent.name(), parent.priority, pcb_off);
+    child.parent = parent.pid;
+    pid::alloc(child);
+    if let Err(e) = init_space(child) {
+        pid::free(child);
+        pg_free(pcb_off, PCB_PAGES);
+        return Err(e);
+    }
+
+    child.v_pool.bitmap.copy_from_slice(parent.v_pool.bitmap);
+    vma::copy(parent, child);
+    shm::dup(child);
+    child.stack_limit = parent.stack_limit;
+    child.brk_start = parent.brk_start;
+    child.brk = parent.brk;
+
+    // pages shared so far are dropped by free_space
+    if let Err(e) = share_pages(parent, child) {
+        free_space(child);
+        pid::free(child);
+        pg_free(pcb_off, PCB_PAGES);
+        return Err(e);
+    }
+
+    // arena blocks of parent are not in free lists of child
+    // child's malloc starts from new pages
+    child.stack -= core::mem::size_of::<IntCtx>();
+    let c = child.int_ctx();
+    unsafe { core::ptr::copy_nonoverlapping(ctx as *const IntCtx, c as *mut IntCtx, 1) };
+    c.eax = 0;
+
+    child.stack -= core::mem::size_of::<KernelCtx>();
+    let k = child.kernel_ctx();
+    k.eip = fork_ret as usize as u32;
+    k.ds = SELECTOR_K_DATA as u32;
+    k.es = SELECTOR_K_DATA as u32;
+
+    let old = disable_int();
+    sched().enqueue(child, Enqueue::New);
+    all().append(child);
+    set_int(old);
+    Ok(child.pid)
+}
+
+// map user pages of parent into child as read only, the first write copies the page
+fn share_pages(parent: &PCB, child: &PCB) -> Result<(), SE> {
+    let lk = u_lock();
+    let _gd = lk.map(|x| x.lock());
+    let klk = k_lock();
+    let _kgd = klk.map(|x| x.lock());
+
+    let r = copy_tables(parent, child);
+
+    // flush tlb of parent, also on failure since some pages are read only now
+    unsafe { asm!("mov cr3, {}", in(reg) cr3(parent.pd)) };
+    r
+}
+
+// caller must hold u_lock and k_lock
+fn copy_tables(parent: &PCB, child: &PCB) -> Result<(), SE> {
+    let src = parent.page_dir().unwrap();
+    let mut dst = child.page_dir().unwrap();
+
+    for i in USER_V_START.pde_i()..OS_MEM_OFF.pde_i() {
+        if !src[i].exists() {
+            continue;
+        }
+
+        // page tables are in kernel pool, which is identity mapped
+        let pt = kernel_pool().p_alloc()?;
+        stat::set(pt, Owner::PageTable);
+        fill_zero(pt, PAGE_SIZE);
+        dst[i].write(pt, DEFAULT_PT_ATTR);
+
+        let mut s = src[i].sub_table();
+        let mut d = dst[i].sub_table();
+
+        for j in 0..pt_len() {
+            if let Some(slot) = s[j].swap_slot() {
+                swap::dup(slot)?;
+                d[j].copy(&s[j]);
+                continue;
+            }
+            if !s[j].exists() {
+                continue;
+            }
+            frame::share(s[j].addr())?;
+            let v = i * large_page_size() + j * PAGE_SIZE;
+            let shared = vma::find(parent, v).map(|x| x.flags & VM_SHARED != 0).unwrap_or(false);
+            if !shared {
+                s[j].set(PG_RW, false);
+            }
+            d[j].copy(&s[j]);
+        }
+    }
+    Ok(())
+}
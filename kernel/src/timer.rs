@@ -1,4 +1,9 @@
+use rlib::alloc_static;
+use rlib::link::{LinkedList, Node};
+
 use crate::asm;
+use crate::int::{disable_int, set_int};
+use crate::thread::ticks;
 
 const COUNTER0_PORT: u16 = 0x40;
 const PIT_CONTROL_PORT: u16 = 0x43;
@@ -8,8 +13,123 @@ pub const IRQ0_FREQUENCY: u32 = 1000;
 const INPUT_FREQUENCY: u32 = 1193180;
 const COUNTER0_VALUE: u32 = INPUT_FREQUENCY / IRQ0_FREQUENCY;
 pub const MIL_SECONDS_PER_INT: u32 = 1000 / IRQ0_FREQUENCY;
+const TIMERS_PADDING: usize = 16;
+
+/// callback run by the timer interrupt with interrupts disabled
+/// an armed timer is linked in the queue, it must not move until it fires or is cancelled
+#[repr(C)]
+pub struct Timer {
+    pointers: [usize; 2],
+    deadline: u32,
+    // ticks between runs, 0 for one shot
+    period: u32,
+    armed: bool,
+    f: fn(usize),
+    arg: usize,
+}
+
+impl Node for Timer {
+    fn pointers_mut(&mut self) -> &mut [usize] {
+        &mut self.pointers
+    }
+
+    fn pointers(&self) -> &[usize] {
+        &self.pointers
+    }
+}
+
+// armed timers sorted by deadline
+alloc_static!(TIMERS, timers, LinkedList<Timer, TIMERS_PADDING>);
+
+impl Timer {
+    pub fn new(f: fn(usize), arg: usize) -> Self {
+        Self { pointers: [0; 2], deadline: 0, period: 0, armed: false, f, arg }
+    }
+
+    /// run once after t ticks, re-arm if armed
+    pub fn start(&mut self, t: u32) {
+        self.start_at((*ticks()).wrapping_add(t));
+    }
+
+    /// run once at deadline, at next tick if it has passed
+    pub fn start_at(&mut self, deadline: u32) {
+        self.arm(deadline, 0);
+    }
+
+    /// run every period ticks, starting after one period
+    pub fn start_periodic(&mut self, period: u32) {
+        assert!(period != 0, "zero timer period");
+        self.arm((*ticks()).wrapping_add(period), period);
+    }
+
+    /// stop the timer, a timer not armed is ignored
+    pub fn cancel(&mut self) {
+        let old = disable_int();
+        if self.armed {
+            timers().remove(self);
+            self.armed = false;
+        }
+        set_int(old);
+    }
+
+    pub fn armed(&self) -> bool {
+        self.armed
+    }
+
+    pub fn deadline(&self) -> u32 {
+        self.deadline
+    }
+
+    fn arm(&mut self, deadline: u32, period: u32) {
+        let old = disable_int();
+        self.cancel();
+        self.deadline = deadline;
+        self.period = period;
+        insert(self);
+        set_int(old);
+    }
+}
+
+// a is earlier than b, ticks wrap around
+fn before(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+// keep the queue sorted, timers of the same deadline run in order of arming
+fn insert(t: &mut Timer) {
+    t.armed = true;
+    match timers().iter().find(|x| before(t.deadline, x.deadline)) {
+        Some(x) => timers().insert_before(x, t),
+        None => timers().append(t),
+    }
+}
+
+/// ticks of mils, at least one tick unless mils is 0
+pub fn mils_to_ticks(mils: u32) -> u32 {
+    if mils == 0 { 0 } else { (mils / MIL_SECONDS_PER_INT).max(1) }
+}
+
+/// whether deadline has passed, ticks wrap around
+pub fn expired(deadline: u32) -> bool {
+    !before(*ticks(), deadline)
+}
+
+/// run expired timers, called by timer interrupt after ticks advanced
+pub fn run() {
+    while let Some(t) = timers().first().filter(|x| expired(x.deadline)) {
+        timers().remove(t);
+        t.armed = false;
+        // re-arm first, so the callback may cancel it
+        if t.period != 0 {
+            t.deadline = t.deadline.wrapping_add(t.period);
+            insert(t);
+        }
+        (t.f)(t.arg);
+    }
+}
 
 pub fn init() {
+    timers().init(0, 1);
     asm::out_b(PIT_CONTROL_PORT, READ_WRITE_LATCH << 4 | COUNTER_MODE << 1);
     asm::out_b(COUNTER0_PORT, (COUNTER0_VALUE & 0xff) as u8);
     asm::out_b(COUNTER0_PORT, (COUNTER0_VALUE >> 8 & 0xff) as u8);
@@ -2,7 +2,6 @@ use core::fmt;
 use core::fmt::Write;
 
 use crate::asm::{in_b, out_b};
-use crate::S_LOCK_SZ;
 use crate::thread::sync::Lock;
 
 const VGA_START: usize = 0xb8000;
@@ -38,11 +37,7 @@ macro_rules! println {
 }
 
 pub fn vga_lock() -> Option<&'static mut Lock> {
-    if unsafe { VGA_LOCK_REF == 0 } {
-        None
-    } else {
-        Some(cst!(VGA_LOCK_REF))
-    }
+    crate::init::locks_ready().then(|| unsafe { &mut VGA_LOCK })
 }
 
 struct Writer {}
@@ -96,8 +91,7 @@ pub fn puts(s: &str) {
     }
 }
 
-pub static mut VGA_LOCK: [u8; S_LOCK_SZ] = [0u8; S_LOCK_SZ];
-pub static mut VGA_LOCK_REF: usize = 0;
+static mut VGA_LOCK: Lock = Lock::named("vga");
 
 const PORT: u16 = 0x3f8;
 
@@ -115,6 +109,16 @@ pub fn init_com1() {
     out_b(PORT + 4, 0x0f);
 }
 
+/// erase the last character of current line
+pub fn back() {
+    unsafe {
+        if VGA_COL > 0 {
+            VGA_COL -= 1;
+            buf()[(VGA_LINES - 1) * VGA_COLS + VGA_COL] = 0x0f20;
+        }
+    }
+}
+
 #[no_mangle]
 pub fn put_char(c: u8) {
     let vga = buf();
@@ -134,6 +134,14 @@ function buildKernel() {
     process.chdir(cwd)
 }
 
+// build user programs into target/x86-unknown-user/release
+function buildUser() {
+    const cwd = process.cwd()
+    process.chdir(rs('user'))
+    cp.execSync('cargo build --release')
+    process.chdir(cwd)
+}
+
 function genLoader() {
     function id(i) {
         let x = i.toString(16)
@@ -214,6 +222,9 @@ switch (os.platform()) {
 // preprocess loader.S to loader.gen.S
 genLoader()
 
+// build user programs
+buildUser()
+
 // build kernel
 buildKernel()
 
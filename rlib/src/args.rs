@@ -28,3 +28,33 @@ impl<'a> core::fmt::Write for SliceWriter<'a> {
         Ok(())
     }
 }
+
+/// max bytes of path, arguments and environment passed to exec
+pub const ARG_MAX: usize = 2048;
+/// max number of arguments and environment strings passed to exec
+pub const ARGS_MAX: usize = 64;
+
+/// pack strings into buf as "path\0argv[0]\0..argv[n]\0envp[0]\0..", return bytes used
+/// return None if buf is too small or a string contains \0
+pub fn pack(buf: &mut [u8], path: &str, argv: &[&str], envp: &[&str]) -> Option<usize> {
+    if argv.len() + envp.len() > ARGS_MAX {
+        return None;
+    }
+    let mut off = 0;
+    for s in core::iter::once(&path).chain(argv.iter()).chain(envp.iter()) {
+        let b = s.as_bytes();
+        if b.contains(&0) || off + b.len() + 1 > buf.len() {
+            return None;
+        }
+        buf[off..off + b.len()].copy_from_slice(b);
+        buf[off + b.len()] = 0;
+        off += b.len() + 1;
+    }
+    Some(off)
+}
+
+/// strings packed by pack
+pub fn unpack(buf: &[u8]) -> impl Iterator<Item=&[u8]> {
+    let end = if buf.last() == Some(&0) { buf.len() - 1 } else { buf.len() };
+    buf[..end].split(|x| *x == 0)
+}
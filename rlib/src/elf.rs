@@ -0,0 +1,142 @@
+use core::mem::size_of;
+
+/// type of loadable segment
+pub const PT_LOAD: u32 = 1;
+
+// permission bits of segment
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 1 << 1;
+pub const PF_R: u32 = 1 << 2;
+
+const MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const CLASS_32: u8 = 1;
+const DATA_LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_386: u16 = 3;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ElfHeader {
+    pub ident: [u8; 16],
+    pub e_type: u16,
+    pub machine: u16,
+    pub version: u32,
+    pub entry: u32,
+    pub ph_off: u32,
+    pub sh_off: u32,
+    pub flags: u32,
+    pub eh_size: u16,
+    pub ph_ent_size: u16,
+    pub ph_num: u16,
+    pub sh_ent_size: u16,
+    pub sh_num: u16,
+    pub sh_str_idx: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub offset: u32,
+    pub v_addr: u32,
+    pub p_addr: u32,
+    pub file_size: u32,
+    pub mem_size: u32,
+    pub flags: u32,
+    pub align: u32,
+}
+
+impl ProgramHeader {
+    pub fn readable(&self) -> bool {
+        self.flags & PF_R != 0
+    }
+
+    pub fn writable(&self) -> bool {
+        self.flags & PF_W != 0
+    }
+
+    pub fn executable(&self) -> bool {
+        self.flags & PF_X != 0
+    }
+
+    /// end of the segment in memory
+    pub fn end(&self) -> usize {
+        self.v_addr as usize + self.mem_size as usize
+    }
+}
+
+// the image is not required to be aligned
+fn read<T: Copy>(data: &[u8], off: usize) -> Option<T> {
+    let end = off.checked_add(size_of::<T>())?;
+    if end > data.len() {
+        return None;
+    }
+    Some(unsafe { core::ptr::read_unaligned(data[off..].as_ptr() as *const T) })
+}
+
+/// executable file of i386, headers are checked by parse
+pub struct Elf<'a> {
+    data: &'a [u8],
+    header: ElfHeader,
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, &'static str> {
+        let h: ElfHeader = read(data, 0).ok_or("elf too small")?;
+        if h.ident[..4] != MAGIC {
+            return Err("not elf");
+        }
+        if h.ident[4] != CLASS_32 || h.ident[5] != DATA_LSB || h.machine != EM_386 {
+            return Err("not i386 elf");
+        }
+        if h.e_type != ET_EXEC {
+            return Err("not executable");
+        }
+        if h.ph_ent_size as usize != size_of::<ProgramHeader>() {
+            return Err("invalid program header");
+        }
+
+        let r = Self { data, header: h };
+        for i in 0..h.ph_num as usize {
+            let ph = r.program_header(i).ok_or("program header out of file")?;
+            if ph.p_type != PT_LOAD {
+                continue;
+            }
+            if ph.file_size > ph.mem_size || (ph.v_addr as usize).checked_add(ph.mem_size as usize).is_none() {
+                return Err("invalid segment size");
+            }
+            let end = (ph.offset as usize).checked_add(ph.file_size as usize);
+            if end.map(|x| x > data.len()).unwrap_or(true) {
+                return Err("segment out of file");
+            }
+        }
+        Ok(r)
+    }
+
+    pub fn header(&self) -> &ElfHeader {
+        &self.header
+    }
+
+    pub fn entry(&self) -> usize {
+        self.header.entry as usize
+    }
+
+    pub fn program_header(&self, i: usize) -> Option<ProgramHeader> {
+        let off = self.header.ph_off as usize + i * size_of::<ProgramHeader>();
+        read(self.data, off)
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item=ProgramHeader> + '_ {
+        (0..self.header.ph_num as usize).filter_map(move |i| self.program_header(i))
+    }
+
+    /// loadable segments
+    pub fn segments(&self) -> impl Iterator<Item=ProgramHeader> + '_ {
+        self.program_headers().filter(|x| x.p_type == PT_LOAD)
+    }
+
+    /// content of segment in file, the rest of segment in memory is zero filled
+    pub fn file_data(&self, ph: &ProgramHeader) -> &'a [u8] {
+        &self.data[ph.offset as usize..ph.offset as usize + ph.file_size as usize]
+    }
+}
@@ -20,6 +20,7 @@ pub mod gdt;
 #[cfg(feature = "sys")]
 pub mod sys;
 pub mod args;
+pub mod elf;
 
 pub fn as_str(x: &[u8]) -> &str {
     for i in 0..x.len() {
@@ -210,6 +210,21 @@ impl<T: 'static + Node, const N: usize> LinkedList<T, N> {
         if self.is_empty() { None } else { self.head().ref_at(self.next_i as usize) }
     }
 
+    pub fn last(&self) -> Option<&'static mut T> {
+        if self.is_empty() { None } else { self.tail().ref_at(self.prev_i as usize) }
+    }
+
+    /// insert n before dst, dst must be in this list
+    pub fn insert_before(&mut self, dst: &mut T, n: &mut T) {
+        self.assert_not_contains(n);
+        self.prepend(dst, n);
+    }
+
+    /// remove n from this list, n must be in this list
+    pub fn remove(&mut self, n: &mut T) {
+        self.detach(n);
+    }
+
     pub fn push_head(&mut self, n: &mut T) {
         self.assert_not_contains(n);
 
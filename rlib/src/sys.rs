@@ -1,3 +1,4 @@
+use crate::args::{ARG_MAX, pack};
 use crate::sys::NR::WRITE;
 
 pub mod NR {
@@ -5,6 +6,77 @@ pub mod NR {
     pub const WRITE: u32 = 1;
     pub const MALLOC: u32 = 2;
     pub const FREE: u32 = 3;
+    pub const BRK: u32 = 4;
+    pub const MMAP: u32 = 5;
+    pub const MUNMAP: u32 = 6;
+    pub const MPROTECT: u32 = 7;
+    pub const FORK: u32 = 8;
+    pub const SHM_OPEN: u32 = 9;
+    pub const SHM_ATTACH: u32 = 10;
+    pub const SHM_DETACH: u32 = 11;
+    pub const MEMINFO: u32 = 12;
+    pub const SCHED_SET: u32 = 13;
+    pub const EXIT: u32 = 14;
+    pub const WAITPID: u32 = 15;
+    pub const GET_PPID: u32 = 16;
+    pub const EXEC: u32 = 17;
+    pub const SHM_UNLINK: u32 = 18;
+}
+
+// protection of mapped memory
+pub const PROT_NONE: u32 = 0;
+pub const PROT_READ: u32 = 1;
+pub const PROT_WRITE: u32 = 1 << 1;
+pub const PROT_EXEC: u32 = 1 << 2;
+
+// scheduling policies of sched_set
+pub const SCHED_NORMAL: u32 = 0;
+pub const SCHED_FIFO: u32 = 1;
+pub const SCHED_RR: u32 = 2;
+
+// returned by mmap on failure
+pub const MAP_FAILED: usize = u32::MAX as usize;
+
+// owners of physical pages, index of MemInfo::owners
+pub const OWNER_KERNEL_HEAP: usize = 0;
+pub const OWNER_PAGE_TABLE: usize = 1;
+pub const OWNER_PCB: usize = 2;
+pub const OWNER_USER: usize = 3;
+pub const MEM_OWNERS: usize = 4;
+
+// size classes of kernel arena
+pub const ARENA_DESCS: usize = 7;
+
+/// usage of blocks of a size class
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+pub struct ArenaInfo {
+    pub blk_sz: u32,
+    // blocks per page
+    pub blocks: u32,
+    // blocks in free list
+    pub frees: u32,
+}
+
+/// memory statistics in pages, filled by meminfo
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+pub struct MemInfo {
+    pub page_size: u32,
+    pub kernel_pages: u32,
+    pub kernel_free: u32,
+    pub user_pages: u32,
+    pub user_free: u32,
+    pub swap_pages: u32,
+    pub swap_used: u32,
+    // allocated pages of each owner
+    pub owners: [u32; MEM_OWNERS],
+    pub arena: [ArenaInfo; ARENA_DESCS],
+    // arena of the caller, for user process
+    pub proc_arena: [ArenaInfo; ARENA_DESCS],
+    // resident and mapped pages of the caller
+    pub rss: u32,
+    pub vm: u32,
 }
 
 
@@ -56,3 +128,103 @@ pub fn free(p: usize) {
     call_1(NR::FREE as u32, p as u32);
 }
 
+// set end of data segment, return current end if failed or addr = 0
+pub fn brk(addr: usize) -> usize {
+    call_1(NR::BRK, addr as u32) as usize
+}
+
+// increase data segment by incr bytes, return the previous end
+pub fn sbrk(incr: isize) -> usize {
+    let old = brk(0);
+    if incr == 0 {
+        return old;
+    }
+    let new = (old as isize + incr) as usize;
+    if brk(new) != new {
+        return MAP_FAILED;
+    }
+    old
+}
+
+// map anonymous memory, addr is a hint, 0 to let kernel choose
+pub fn mmap(addr: usize, len: usize, prot: u32) -> usize {
+    call_3(NR::MMAP, addr as u32, len as u32, prot) as usize
+}
+
+pub fn munmap(addr: usize, len: usize) -> i32 {
+    call_2(NR::MUNMAP, addr as u32, len as u32) as i32
+}
+
+pub fn mprotect(addr: usize, len: usize, prot: u32) -> i32 {
+    call_3(NR::MPROTECT, addr as u32, len as u32, prot) as i32
+}
+
+// return pid of the child in parent, 0 in child, u32::MAX if failed
+pub fn fork() -> usize {
+    call_0(NR::FORK) as usize
+}
+
+// get id of shared memory segment with key, create it if not exists, return -1 if failed
+pub fn shm_open(key: u32, size: usize) -> i32 {
+    call_2(NR::SHM_OPEN, key, size as u32) as i32
+}
+
+// map shared memory segment at addr, 0 to let kernel choose
+pub fn shm_attach(id: i32, addr: usize) -> usize {
+    call_2(NR::SHM_ATTACH, id as u32, addr as u32) as usize
+}
+
+pub fn shm_detach(addr: usize) -> i32 {
+    call_1(NR::SHM_DETACH, addr as u32) as i32
+}
+
+// remove the segment of key, it is freed once no process attaches it, return -1 if not found
+pub fn shm_unlink(key: u32) -> i32 {
+    call_1(NR::SHM_UNLINK, key) as i32
+}
+
+// fill memory statistics, return -1 if failed
+pub fn meminfo(info: &mut MemInfo) -> i32 {
+    call_1(NR::MEMINFO, info as *mut _ as u32) as i32
+}
+
+// set scheduling policy and priority of the caller or its child pid, 0 for the caller, return -1 if failed
+// real time priority is 1..32, higher runs first, only init may raise a priority or grant a real time policy
+pub fn sched_set(pid: usize, policy: u32, priority: u8) -> i32 {
+    call_3(NR::SCHED_SET, pid as u32, policy, priority as u32) as i32
+}
+
+// terminate current process, code is returned to parent by wait
+pub fn exit(code: i32) -> ! {
+    call_1(NR::EXIT, code as u32);
+    loop {}
+}
+
+// wait for child pid to exit, any child if pid is 0, store its exit code in code
+// return id of the child, u32::MAX if failed
+pub fn waitpid(pid: usize, code: &mut i32) -> usize {
+    call_2(NR::WAITPID, pid as u32, code as *mut _ as u32) as usize
+}
+
+pub fn wait(code: &mut i32) -> usize {
+    waitpid(0, code)
+}
+
+pub fn getpid() -> usize {
+    call_0(NR::GET_PID) as usize
+}
+
+// pid of parent, 0 if the parent exited
+pub fn getppid() -> usize {
+    call_0(NR::GET_PPID) as usize
+}
+
+// replace current process with program path, return -1 if failed
+pub fn exec(path: &str, argv: &[&str], envp: &[&str]) -> i32 {
+    let mut buf = [0u8; ARG_MAX];
+    let n = match pack(&mut buf, path, argv, envp) {
+        Some(n) => n,
+        None => return -1,
+    };
+    call_3(NR::EXEC, buf.as_ptr() as u32, n as u32, (argv.len() | envp.len() << 16) as u32) as i32
+}
@@ -0,0 +1,18 @@
+use rlib::args::{pack, unpack};
+
+#[test]
+fn test_pack() {
+    let mut buf = [0u8; 64];
+    let n = pack(&mut buf, "/bin/sh", &["sh", "-c"], &["HOME=/"]).unwrap();
+    assert_eq!(&buf[..n], b"/bin/sh\0sh\0-c\0HOME=/\0");
+
+    let v: Vec<_> = unpack(&buf[..n]).collect();
+    assert_eq!(v, [&b"/bin/sh"[..], b"sh", b"-c", b"HOME=/"]);
+
+    // an empty argument is kept
+    let n = pack(&mut buf, "a", &[""], &[]).unwrap();
+    assert_eq!(unpack(&buf[..n]).count(), 2);
+
+    assert_eq!(pack(&mut buf[..8], "/bin/sh", &["sh"], &[]), None);
+    assert_eq!(pack(&mut buf, "a\0b", &[], &[]), None);
+}
@@ -0,0 +1,76 @@
+use rlib::elf::{Elf, ElfHeader, PF_R, PF_W, PF_X, ProgramHeader, PT_LOAD};
+
+fn bytes<T>(x: &T) -> &[u8] {
+    unsafe { core::slice::from_raw_parts(x as *const T as *const u8, core::mem::size_of::<T>()) }
+}
+
+// header, two program headers, then the code
+fn image(segments: &[ProgramHeader]) -> Vec<u8> {
+    let mut h = ElfHeader::default();
+    h.ident[..6].copy_from_slice(&[0x7f, b'E', b'L', b'F', 1, 1]);
+    h.e_type = 2;
+    h.machine = 3;
+    h.entry = 0x8049000;
+    h.ph_off = core::mem::size_of::<ElfHeader>() as u32;
+    h.ph_ent_size = core::mem::size_of::<ProgramHeader>() as u16;
+    h.ph_num = segments.len() as u16;
+
+    let mut v = bytes(&h).to_vec();
+    for ph in segments {
+        v.extend_from_slice(bytes(ph));
+    }
+    v.resize(0x1000, 0);
+    v.extend_from_slice(&[0x90, 0x90, 0xeb, 0xfe]);
+    v
+}
+
+fn segment(p_type: u32, offset: u32, v_addr: u32, file_size: u32, mem_size: u32, flags: u32) -> ProgramHeader {
+    ProgramHeader { p_type, offset, v_addr, p_addr: v_addr, file_size, mem_size, flags, align: 0x1000 }
+}
+
+#[test]
+fn test_parse() {
+    let img = image(&[
+        segment(PT_LOAD, 0x1000, 0x8049000, 4, 4, PF_R | PF_X),
+        segment(6, 0x34, 0x8048034, 0x40, 0x40, PF_R),
+        segment(PT_LOAD, 0x1000, 0x804a000, 0, 0x2000, PF_R | PF_W),
+    ]);
+    let elf = Elf::parse(&img).unwrap();
+    assert_eq!(elf.entry(), 0x8049000);
+    assert_eq!(elf.program_headers().count(), 3);
+
+    let segs: Vec<_> = elf.segments().collect();
+    assert_eq!(segs.len(), 2);
+    assert!(segs[0].executable() && !segs[0].writable());
+    assert_eq!(elf.file_data(&segs[0]), &[0x90, 0x90, 0xeb, 0xfe]);
+    assert!(segs[1].writable() && segs[1].readable());
+    assert_eq!(elf.file_data(&segs[1]).len(), 0);
+    assert_eq!(segs[1].end(), 0x804c000);
+}
+
+#[test]
+fn test_invalid() {
+    let code = segment(PT_LOAD, 0x1000, 0x8049000, 4, 4, PF_R | PF_X);
+
+    assert_eq!(Elf::parse(&[0x7f, b'E']).err(), Some("elf too small"));
+
+    let mut img = image(&[code]);
+    img[1] = b'X';
+    assert_eq!(Elf::parse(&img).err(), Some("not elf"));
+
+    let mut img = image(&[code]);
+    img[4] = 2;
+    assert_eq!(Elf::parse(&img).err(), Some("not i386 elf"));
+
+    // segment beyond the end of file
+    let img = image(&[segment(PT_LOAD, 0x1000, 0x8049000, 0x100, 0x100, PF_R)]);
+    assert_eq!(Elf::parse(&img).err(), Some("segment out of file"));
+
+    let img = image(&[segment(PT_LOAD, 0x1000, 0x8049000, 4, 2, PF_R)]);
+    assert_eq!(Elf::parse(&img).err(), Some("invalid segment size"));
+
+    // program headers beyond the end of file
+    let mut img = image(&[code]);
+    img.truncate(core::mem::size_of::<ElfHeader>() + 8);
+    assert_eq!(Elf::parse(&img).err(), Some("program header out of file"));
+}
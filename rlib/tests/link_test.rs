@@ -99,4 +99,34 @@ fn test1() {
     }
 
     println!("{:?}", y);
-}
\ No newline at end of file
+}
+alloc_static!(M0, m0, LNode);
+alloc_static!(M1, m1, LNode);
+alloc_static!(M2, m2, LNode);
+
+#[test]
+fn test_insert_remove() {
+    let mut li: LinkedList<LNode, 256> = LinkedList::default();
+    li.init(0, 1);
+
+    for x in [m0(), m1(), m2()].into_iter().enumerate() {
+        x.1.id = x.0
+    }
+
+    li.append(m0());
+    li.append(m2());
+    li.insert_before(m2(), m1());
+
+    let v: Vec<_> = li.iter().map(|x| x.id).collect();
+    assert_eq!(v, vec![0, 1, 2]);
+    assert_eq!(li.last().unwrap().id, 2);
+
+    li.remove(m1());
+    let v: Vec<_> = li.iter().map(|x| x.id).collect();
+    assert_eq!(v, vec![0, 2]);
+
+    li.remove(m0());
+    li.remove(m2());
+    assert!(li.is_empty());
+    assert!(li.last().is_none());
+}
@@ -0,0 +1,46 @@
+static mut ARGC: usize = 0;
+static mut ARGV: usize = 0;
+static mut ENVP: usize = 0;
+
+// called by _start
+pub(crate) fn init(argc: usize, argv: *const *const u8, envp: *const *const u8) {
+    unsafe {
+        ARGC = argc;
+        ARGV = argv as usize;
+        ENVP = envp as usize;
+    }
+}
+
+// string terminated by 0, copied onto stack by the kernel
+fn c_str(p: *const u8) -> &'static str {
+    unsafe {
+        let mut len = 0;
+        while *p.add(len) != 0 {
+            len += 1;
+        }
+        core::str::from_utf8_unchecked(core::slice::from_raw_parts(p, len))
+    }
+}
+
+// strings of a null terminated pointer array
+fn strings(p: usize) -> impl Iterator<Item=&'static str> {
+    let p = p as *const *const u8;
+    (0..).map(move |i| unsafe { *p.add(i) }).take_while(|x| !x.is_null()).map(c_str)
+}
+
+/// arguments of the program, the first one is usually its name
+pub fn args() -> impl Iterator<Item=&'static str> {
+    strings(unsafe { ARGV }).take(unsafe { ARGC })
+}
+
+/// environment variables as (key, value)
+pub fn vars() -> impl Iterator<Item=(&'static str, &'static str)> {
+    strings(unsafe { ENVP }).map(|x| match x.find('=') {
+        Some(i) => (&x[..i], &x[i + 1..]),
+        None => (x, ""),
+    })
+}
+
+pub fn var(key: &str) -> Option<&'static str> {
+    vars().find(|x| x.0 == key).map(|x| x.1)
+}
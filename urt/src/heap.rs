@@ -0,0 +1,42 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem::size_of;
+
+use rlib::sys::{free, malloc};
+
+// blocks of malloc follow a 12 bytes arena header
+const MIN_ALIGN: usize = 4;
+
+/// global allocator over malloc and free syscalls
+/// for larger alignment, the block is over allocated and the pointer from malloc is kept before the returned address
+pub struct Heap;
+
+unsafe impl GlobalAlloc for Heap {
+    unsafe fn alloc(&self, l: Layout) -> *mut u8 {
+        if l.align() <= MIN_ALIGN {
+            return malloc(l.size()) as *mut u8;
+        }
+        let p = malloc(l.size() + l.align());
+        if p == 0 {
+            return core::ptr::null_mut();
+        }
+        let a = (p + size_of::<usize>() + l.align() - 1) & !(l.align() - 1);
+        *((a - size_of::<usize>()) as *mut usize) = p;
+        a as *mut u8
+    }
+
+    unsafe fn dealloc(&self, p: *mut u8, l: Layout) {
+        if l.align() <= MIN_ALIGN {
+            free(p as usize);
+        } else {
+            free(*((p as usize - size_of::<usize>()) as *const usize));
+        }
+    }
+}
+
+#[global_allocator]
+static HEAP: Heap = Heap;
+
+#[alloc_error_handler]
+fn alloc_error(l: Layout) -> ! {
+    panic!("out of memory, size = {} align = {}", l.size(), l.align())
+}
@@ -0,0 +1,31 @@
+#![no_std]
+#![feature(asm)]
+#![feature(global_asm)]
+#![feature(alloc_error_handler)]
+
+//! runtime of user programs
+//!
+//! ```ignore
+//! #![no_std]
+//! #![no_main]
+//! use urt::println;
+//!
+//! fn main() -> i32 {
+//!     println!("hello from {}", urt::env::args().next().unwrap());
+//!     0
+//! }
+//!
+//! urt::entry!(main);
+//! ```
+
+extern crate alloc;
+
+pub use rlib::sys;
+pub use rlib::sys::exit;
+
+pub mod env;
+pub mod heap;
+pub mod io;
+mod start;
+
+pub use start::ExitCode;
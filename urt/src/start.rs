@@ -0,0 +1,71 @@
+use core::panic::PanicInfo;
+
+use rlib::sys::exit;
+
+use crate::env;
+
+// the kernel enters with argc, argv and envp on stack, see thread::exec of kernel
+global_asm!(
+    ".global _start",
+    "_start:",
+    "xor ebp, ebp",
+    "mov eax, esp",
+    "and esp, -16",
+    "sub esp, 12",
+    "push eax",
+    "call urt_start",
+    "ud2",
+);
+
+extern "Rust" {
+    // defined by entry!
+    fn __urt_main() -> i32;
+}
+
+#[no_mangle]
+extern "C" fn urt_start(sp: *const usize) -> ! {
+    unsafe {
+        let argc = *sp;
+        let argv = sp.add(1) as *const *const u8;
+        env::init(argc, argv, argv.add(argc + 1));
+        exit(__urt_main())
+    }
+}
+
+/// return type of main
+pub trait ExitCode {
+    fn code(self) -> i32;
+}
+
+impl ExitCode for () {
+    fn code(self) -> i32 {
+        0
+    }
+}
+
+impl ExitCode for i32 {
+    fn code(self) -> i32 {
+        self
+    }
+}
+
+/// declare main of a user program, main returns () or an exit code
+/// the process exits when main returns
+#[macro_export]
+macro_rules! entry {
+    ($main: path) => {
+        #[no_mangle]
+        fn __urt_main() -> i32 {
+            $crate::ExitCode::code($main())
+        }
+    };
+}
+
+// exit code of panic, like rust std
+const PANIC_EXIT: i32 = 101;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    crate::println!("{}", info);
+    exit(PANIC_EXIT)
+}
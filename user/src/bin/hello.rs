@@ -0,0 +1,13 @@
+#![no_std]
+#![no_main]
+
+use urt::println;
+
+fn main() {
+    for (i, x) in urt::env::args().enumerate() {
+        println!("argv[{}] = {}", i, x);
+    }
+    println!("hello from pid {}", urt::sys::getpid());
+}
+
+urt::entry!(main);
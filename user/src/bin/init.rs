@@ -0,0 +1,34 @@
+#![no_std]
+#![no_main]
+
+use urt::println;
+use urt::sys::{exec, exit, fork, getpid, wait};
+
+// programs started by init
+const PROGRAMS: [&str; 1] = ["/bin/hello"];
+
+fn main() {
+    println!("init: running as pid {}", getpid());
+    for path in PROGRAMS {
+        match fork() {
+            0 => {
+                exec(path, &[path], &["PATH=/bin"]);
+                println!("init: cannot exec {}", path);
+                exit(127);
+            }
+            usize::MAX => println!("init: cannot fork for {}", path),
+            pid => println!("init: started {} as pid {}", path, pid),
+        }
+    }
+
+    // collect children and orphans adopted from exited processes
+    loop {
+        let mut code = 0;
+        let pid = wait(&mut code);
+        if pid != usize::MAX {
+            println!("init: pid {} exited with code {}", pid, code);
+        }
+    }
+}
+
+urt::entry!(main);